@@ -1,6 +1,252 @@
-﻿pub const IMAGE_WIDTH: u64 = 480;
-pub const IMAGE_HEIGHT: u64 = ((IMAGE_WIDTH as f64) / ASPECT_RATIO) as u64;
-pub const ASPECT_RATIO: f64 = 1.0;
-pub const SAMPLES_PER_PIXEL: u64 = 2000;
-pub const MAX_DEPTH: u64 = 200;
-pub const SCENE_SELECTOR: u8 = 5;
+use std::{fs, path::PathBuf};
+
+use clap::{builder::RangedU64ValueParser, error::ErrorKind, CommandFactory, Parser, ValueEnum};
+
+use crate::{
+    hit::bvh::DEFAULT_LEAF_SIZE,
+    render::{
+        integrator::IntegratorName,
+        tonemap::{Operator, Transfer},
+    },
+};
+
+pub const DEFAULT_ASPECT_RATIO: f64 = 1.0;
+
+fn positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        Ok(_) => Err(format!("{} is not a positive number", s)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SceneName {
+    Random,
+    Earth,
+    Cornell,
+    CornellSmoke,
+    Final,
+    CornellTest,
+}
+
+/// A Monte Carlo path tracer
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Config {
+    /// Image width in pixels
+    #[arg(short = 'W', long, default_value_t = 480, value_parser = clap::value_parser!(u32).range(2..))]
+    pub width: u32,
+
+    /// Image height in pixels [default: width / aspect]
+    #[arg(short = 'H', long, conflicts_with = "aspect", value_parser = clap::value_parser!(u32).range(2..))]
+    pub height: Option<u32>,
+
+    /// Aspect ratio (width / height) [default: 1.0, or width / height]
+    #[arg(short, long, value_parser = positive)]
+    pub aspect: Option<f64>,
+
+    /// Samples per pixel
+    #[arg(short, long = "spp", default_value_t = 2000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub samples_per_pixel: u64,
+
+    /// Sample each pixel until its noise drops below --noise-threshold
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Samples every pixel gets before adaptive sampling may stop it
+    #[arg(long, default_value_t = 16, requires = "adaptive")]
+    pub min_spp: u64,
+
+    /// Most samples adaptive sampling spends on a pixel [default: --spp]
+    #[arg(long, requires = "adaptive", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_spp: Option<u64>,
+
+    /// Relative standard error of a pixel's luminance at which adaptive sampling stops
+    #[arg(long, default_value_t = 0.01, requires = "adaptive")]
+    pub noise_threshold: f64,
+
+    /// Also write a heatmap of the samples spent per pixel to this path
+    #[arg(long)]
+    pub heatmap: Option<PathBuf>,
+
+    /// Samples added to every pixel per progressive pass
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u64).range(1..))]
+    pub pass_spp: u64,
+
+    /// Periodically save the accumulated samples to this file, and update the outputs with them
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Minimum number of seconds between two checkpoints
+    #[arg(long, default_value_t = 60.0)]
+    pub checkpoint_interval: f64,
+
+    /// Continue the render saved in this checkpoint up to --spp, checkpointing back into it
+    /// unless --checkpoint is given
+    #[arg(long, conflicts_with = "seed")]
+    pub resume: Option<PathBuf>,
+
+    /// Rendering algorithm
+    #[arg(long, value_enum, default_value_t = IntegratorName::Path)]
+    pub integrator: IntegratorName,
+
+    /// Maximum number of bounces per path
+    #[arg(short = 'd', long, default_value_t = 200)]
+    pub max_depth: u64,
+
+    /// Bounces before Russian roulette may end a path
+    #[arg(long, default_value_t = 3)]
+    pub rr_min_bounces: u64,
+
+    /// Photons traced from the lights per pass of the photon mapping integrators
+    #[arg(long, default_value_t = 200_000)]
+    pub photons: usize,
+
+    /// Radius within which photons light a point [default: a hundredth of the size of the
+    /// scene]
+    #[arg(long)]
+    pub photon_radius: Option<f64>,
+
+    /// Fraction of the photons progressive photon mapping keeps from pass to pass as it shrinks
+    /// the radius
+    #[arg(long, default_value_t = 2.0 / 3.0)]
+    pub ppm_alpha: f64,
+
+    /// Distance within which occluders darken the ambient occlusion integrator [default:
+    /// unlimited]
+    #[arg(long)]
+    pub ao_distance: Option<f64>,
+
+    /// Most primitives a BVH leaf may hold
+    #[arg(long, default_value_t = DEFAULT_LEAF_SIZE, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub bvh_leaf_size: usize,
+
+    /// Print node count, depth and estimated traversal cost of the BVHs built for the scene
+    #[arg(long)]
+    pub bvh_stats: bool,
+
+    /// Instead of rendering, time this many camera rays to their first hit and report the
+    /// throughput
+    #[arg(long, value_name = "RAYS")]
+    pub benchmark: Option<u64>,
+
+    /// Built-in scene to render
+    #[arg(long, value_enum, default_value_t = SceneName::Cornell)]
+    pub scene: SceneName,
+
+    /// Scene description file to render instead of a built-in scene, or a glTF 2.0 file
+    /// (`.gltf`/`.glb`)
+    #[arg(long, conflicts_with = "scene")]
+    pub scene_file: Option<PathBuf>,
+
+    /// Output image path, may be repeated; .exr and .pfm keep linear HDR radiance
+    #[arg(short, long, default_value = "output/test.png")]
+    pub output: Vec<PathBuf>,
+
+    /// Exposure adjustment in stops for 8-bit outputs
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f64,
+
+    /// Tone mapping operator for 8-bit outputs
+    #[arg(long, value_enum, default_value_t = Operator::Clamp)]
+    pub tonemap: Operator,
+
+    /// Transfer function encoding 8-bit outputs
+    #[arg(long, value_enum, default_value_t = Transfer::Gamma2)]
+    pub transfer: Transfer,
+
+    /// Number of worker threads [default: number of cores]
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Edge length of the square tiles the image is split into for rendering
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: u32,
+
+    /// Seed for the random number generators [default: random]
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl Config {
+    /// Parse the command line, filling in the values that depend on each other
+    pub fn from_args() -> Config {
+        let mut cfg = Config::parse();
+        if cfg.height.is_none() {
+            let height = cfg.width as f64 / cfg.aspect_ratio();
+            if !(2.0..=u32::MAX as f64).contains(&height) {
+                Config::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!(
+                            "--aspect {} makes the image {} pixels high, it must be from 2 to {}",
+                            cfg.aspect_ratio(),
+                            height.floor(),
+                            u32::MAX
+                        ),
+                    )
+                    .exit();
+            }
+        }
+        cfg.seed.get_or_insert_with(rand::random);
+        if cfg.checkpoint.is_none() {
+            cfg.checkpoint.clone_from(&cfg.resume);
+        }
+        cfg
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        match (self.aspect, self.height) {
+            (Some(aspect), _) => aspect,
+            (None, Some(height)) => self.width as f64 / height as f64,
+            (None, None) => DEFAULT_ASPECT_RATIO,
+        }
+    }
+
+    pub fn image_width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn image_height(&self) -> u32 {
+        self.height
+            .unwrap_or_else(|| ((self.width as f64) / self.aspect_ratio()) as u32)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed.unwrap_or_default()
+    }
+
+    /// Hash of the scene and of every setting that changes the image apart from the sample
+    /// counts and the seed, to tell whether a checkpoint can be resumed with them
+    pub fn settings_hash(&self) -> u64 {
+        let scene = match &self.scene_file {
+            Some(path) => fs::canonicalize(path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string(),
+            None => format!("{:?}", self.scene),
+        };
+        let adaptive = self
+            .adaptive
+            .then_some((self.min_spp, self.noise_threshold));
+        let settings = format!(
+            "{:?}",
+            (
+                scene,
+                self.aspect_ratio(),
+                self.pass_spp,
+                adaptive,
+                self.integrator,
+                self.max_depth,
+                self.rr_min_bounces,
+                (self.photons, self.photon_radius, self.ppm_alpha),
+                self.ao_distance,
+            )
+        );
+        // FNV-1a, which unlike `DefaultHasher` is the same for every build
+        settings.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
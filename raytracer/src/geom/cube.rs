@@ -3,7 +3,7 @@ use crate::{
         aabb::AABB,
         hittable::{HitRecord, Hittable},
     },
-    render::mat::Material,
+    render::{mat::Material, sampler::Sampler},
     world::hittablelist::HittableList,
 };
 
@@ -85,8 +85,8 @@ impl Cube {
 }
 
 impl Hittable for Cube {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(r, t_min, t_max)
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        self.sides.hit(r, t_min, t_max, rng)
     }
 
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
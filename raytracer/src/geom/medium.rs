@@ -0,0 +1,363 @@
+use std::{fs, path::Path};
+
+use rand::Rng;
+
+use crate::{
+    geom::{
+        ray::Ray,
+        vec3::{Point3, Vec3},
+    },
+    hit::{
+        aabb::AABB,
+        hittable::{HitRecord, Hittable},
+    },
+    render::{
+        mat::{Isotropic, Material},
+        perlin::Perlin,
+        sampler::Sampler,
+        texture::Texture,
+    },
+};
+
+/// Where `r` is inside `boundary` between `t_min` and `t_max`, if anywhere
+///
+/// Only the first two crossings of the boundary are used, so it must be convex.
+fn inside<H: Hittable>(
+    boundary: &H,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rng: &mut Sampler,
+) -> Option<(f64, f64)> {
+    let enter = boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY, rng)?;
+    let exit = boundary.hit(r, enter.t + 0.0001, f64::INFINITY, rng)?;
+
+    let t_enter = enter.t.max(t_min).max(0.0);
+    let t_exit = exit.t.min(t_max);
+    (t_enter < t_exit).then_some((t_enter, t_exit))
+}
+
+/// A scattering event at `t` along `r`
+fn scatter_record<'a>(r: &Ray, t: f64, phase_function: &'a dyn Material) -> HitRecord<'a> {
+    HitRecord {
+        position: r.at(t),
+        // a point in a volume has no surface, any normal will do
+        normal: Vec3::new(1.0, 0.0, 0.0),
+        t,
+        u: 0.0,
+        v: 0.0,
+        front_face: true,
+        material: phase_function,
+        color: None,
+    }
+}
+
+/// Participating medium of constant density filling a convex boundary
+///
+/// A ray scatters inside after an exponentially distributed distance, or passes through.
+pub struct ConstantMedium<H: Hittable, M: Material> {
+    boundary: H,
+    density: f64,
+    phase_function: M,
+}
+
+impl<H: Hittable, T: Texture> ConstantMedium<H, Isotropic<T>> {
+    pub fn new(boundary: H, density: f64, albedo: T) -> ConstantMedium<H, Isotropic<T>> {
+        ConstantMedium::with_phase(boundary, density, Isotropic::new(albedo))
+    }
+}
+
+impl<H: Hittable, M: Material> ConstantMedium<H, M> {
+    pub fn with_phase(boundary: H, density: f64, phase_function: M) -> ConstantMedium<H, M> {
+        ConstantMedium {
+            boundary,
+            density,
+            phase_function,
+        }
+    }
+}
+
+impl<H: Hittable, M: Material> Hittable for ConstantMedium<H, M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = inside(&self.boundary, r, t_min, t_max, rng)?;
+        let ray_length = r.direction().length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = -(1.0 - rng.gen::<f64>()).ln() / self.density;
+        if hit_distance > distance_inside {
+            return None;
+        }
+        let t = t_enter + hit_distance / ray_length;
+        Some(scatter_record(r, t, &self.phase_function))
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        match inside(&self.boundary, r, t_min, t_max, rng) {
+            Some((t_enter, t_exit)) => {
+                (-self.density * (t_exit - t_enter) * r.direction().length()).exp()
+            }
+            None => 1.0,
+        }
+    }
+}
+
+/// Density of a medium varying in space
+pub trait Density: Send + Sync {
+    fn density(&self, p: Point3) -> f64;
+    /// Upper bound of the density anywhere, which tracking uses as its majorant
+    fn max_density(&self) -> f64;
+}
+
+/// Density following Perlin turbulence, reaching `density` where the turbulence peaks
+pub struct NoiseDensity {
+    noise: Perlin,
+    scale: f64,
+    density: f64,
+}
+
+impl NoiseDensity {
+    pub fn new<R: Rng + ?Sized>(scale: f64, density: f64, rng: &mut R) -> NoiseDensity {
+        NoiseDensity {
+            noise: Perlin::new(rng),
+            scale,
+            density,
+        }
+    }
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, p: Point3) -> f64 {
+        self.density * self.noise.turb(&p, self.scale, 7).min(1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+/// Density sampled at the centres of the cells of a regular grid over a box, interpolated
+/// trilinearly and zero outside
+pub struct VoxelGrid {
+    bounds: AABB,
+    dims: [usize; 3],
+    /// x varies fastest, then y, then z
+    values: Vec<f64>,
+    max: f64,
+}
+
+impl VoxelGrid {
+    pub fn new(bounds: AABB, dims: [usize; 3], values: Vec<f64>) -> Result<VoxelGrid, String> {
+        let cells = dims.iter().try_fold(1usize, |n, &d| n.checked_mul(d));
+        if cells != Some(values.len()) || values.is_empty() {
+            return Err(format!(
+                "{} grid values do not fill a {}x{}x{} grid",
+                values.len(),
+                dims[0],
+                dims[1],
+                dims[2]
+            ));
+        }
+        if let Some(value) = values.iter().find(|v| !v.is_finite() || **v < 0.0) {
+            return Err(format!("invalid density {} in grid", value));
+        }
+        let max = values.iter().copied().fold(0.0, f64::max);
+        Ok(VoxelGrid {
+            bounds,
+            dims,
+            values,
+            max,
+        })
+    }
+
+    /// Read a Mitsuba `.vol` grid of 32-bit floats, averaging the channels of every cell and
+    /// multiplying them by `scale`
+    pub fn load<P: AsRef<Path>>(path: P, scale: f64) -> Result<VoxelGrid, String> {
+        let path = path.as_ref();
+        let bytes =
+            fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        let error = |err: &str| format!("{}: {}", path.display(), err);
+        if bytes.len() < 48 || &bytes[..3] != b"VOL" || bytes[3] != 3 {
+            return Err(error("not a version 3 .vol file"));
+        }
+        let int = |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let float = |at: usize| f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as f64;
+        if int(4) != 1 {
+            return Err(error("only 32-bit float grids are supported"));
+        }
+        let dims = [int(8), int(12), int(16)].map(|n| n.max(0) as usize);
+        let channels = int(20).max(1) as usize;
+        let bounds = AABB::new(
+            Point3::new(float(24), float(28), float(32)),
+            Point3::new(float(36), float(40), float(44)),
+        );
+        let size = dims
+            .iter()
+            .try_fold(4 * channels, |n, &d| n.checked_mul(d))
+            .ok_or_else(|| error("grid is too large"))?;
+        let data = &bytes[48..];
+        if data.len() < size {
+            return Err(error("grid data is truncated"));
+        }
+        let values = data[..size]
+            .chunks_exact(4 * channels)
+            .map(|cell| {
+                let sum: f64 = cell
+                    .chunks_exact(4)
+                    .map(|v| f32::from_le_bytes(v.try_into().unwrap()) as f64)
+                    .sum();
+                scale * sum / channels as f64
+            })
+            .collect();
+        VoxelGrid::new(bounds, dims, values).map_err(|err| error(&err))
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.dims[1] + y) * self.dims[0] + x]
+    }
+}
+
+impl Density for VoxelGrid {
+    fn density(&self, p: Point3) -> f64 {
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        let mut cell = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let t = (p.get(axis) - min.get(axis)) / (max.get(axis) - min.get(axis));
+            if !(0.0..=1.0).contains(&t) {
+                return 0.0;
+            }
+            let n = self.dims[axis];
+            let x = (t * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            cell[axis] = (x as usize).min(n.saturating_sub(2));
+            frac[axis] = if n > 1 { x - cell[axis] as f64 } else { 0.0 };
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut idx = [0; 3];
+            for axis in 0..3 {
+                let upper = (corner >> axis) & 1 == 1;
+                idx[axis] = (cell[axis] + upper as usize).min(self.dims[axis] - 1);
+                weight *= if upper { frac[axis] } else { 1.0 - frac[axis] };
+            }
+            density += weight * self.value(idx[0], idx[1], idx[2]);
+        }
+        density
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+/// Participating medium whose density varies inside a convex boundary
+///
+/// Scattering distances are sampled by delta tracking and shadow rays are attenuated by ratio
+/// tracking, both stepping through the medium as if it had the density's majorant everywhere.
+pub struct HeterogeneousMedium<H: Hittable, D: Density, M: Material> {
+    boundary: H,
+    density: D,
+    phase_function: M,
+}
+
+impl<H: Hittable, D: Density, M: Material> HeterogeneousMedium<H, D, M> {
+    pub fn new(boundary: H, density: D, phase_function: M) -> HeterogeneousMedium<H, D, M> {
+        HeterogeneousMedium {
+            boundary,
+            density,
+            phase_function,
+        }
+    }
+
+    /// Step from `t` to the next tentative collision along `r`, given `majorant / |d|`
+    fn step(t: f64, majorant: f64, rng: &mut Sampler) -> f64 {
+        t - (1.0 - rng.gen::<f64>()).ln() / majorant
+    }
+}
+
+impl<H: Hittable, D: Density, M: Material> Hittable for HeterogeneousMedium<H, D, M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        let (mut t, t_exit) = inside(&self.boundary, r, t_min, t_max, rng)?;
+        let majorant = self.density.max_density() * r.direction().length();
+        if majorant <= 0.0 {
+            return None;
+        }
+        // delta tracking: accept tentative collisions in proportion to the real density
+        loop {
+            t = Self::step(t, majorant, rng);
+            if t >= t_exit {
+                return None;
+            }
+            let density = self.density.density(r.at(t));
+            if rng.gen::<f64>() * self.density.max_density() < density {
+                return Some(scatter_record(r, t, &self.phase_function));
+            }
+        }
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        let Some((mut t, t_exit)) = inside(&self.boundary, r, t_min, t_max, rng) else {
+            return 1.0;
+        };
+        let majorant = self.density.max_density() * r.direction().length();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        // ratio tracking: every tentative collision lets the fraction of null density through
+        let mut transmittance = 1.0;
+        loop {
+            t = Self::step(t, majorant, rng);
+            if t >= t_exit {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density.density(r.at(t)) / self.density.max_density();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> AABB {
+        AABB::new(Point3::zero(), Point3::ones())
+    }
+
+    /// A `.vol` header for a float grid of `dims` cells with `channels` channels over the unit box
+    fn vol_header(dims: [i32; 3], channels: i32) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for n in [1, dims[0], dims[1], dims[2], channels] {
+            bytes.extend(n.to_le_bytes());
+        }
+        for x in [0.0f32, 0.0, 0.0, 1.0, 1.0, 1.0] {
+            bytes.extend(x.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_grids_too_large_to_address() {
+        let path = std::env::temp_dir().join(format!("grid-{}.vol", std::process::id()));
+        fs::write(&path, vol_header([i32::MAX; 3], i32::MAX)).unwrap();
+        let grid = VoxelGrid::load(&path, 1.0);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(grid, Err(err) if err.ends_with("grid is too large")));
+    }
+
+    #[test]
+    fn rejects_invalid_densities() {
+        for value in [-1.0, f64::NAN, f64::INFINITY] {
+            let grid = VoxelGrid::new(unit_box(), [2, 1, 1], vec![0.5, value]);
+            assert!(matches!(grid, Err(err) if err.starts_with("invalid density")));
+        }
+        assert!(VoxelGrid::new(unit_box(), [2, 1, 1], vec![0.5, 0.0]).is_ok());
+    }
+}
@@ -1,79 +1,651 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rand::Rng;
 
 use crate::{
     hit::{
         aabb::AABB,
+        bvh::{BVHStats, BVH},
         hittable::{HitRecord, Hittable},
     },
-    render::mat::Material,
-    world::hittablelist::HittableList,
+    render::{
+        color::Color,
+        mat::{DiffuseLight, Lambertian, Material},
+        principled::Principled,
+        sampler::Sampler,
+        texture::{ConstantTexture, ImageTexture, Texture, VertexColorTexture},
+    },
 };
 
 use crate::geom::{
+    ply::read_ply,
     ray::Ray,
-    triangle::Triangle,
+    triangle::{hit_triangle, triangle_bbox},
     vec3::{Point3, Vec3},
 };
 
+fn color(c: [f32; 3]) -> Color {
+    Color::new(c[0] as f64, c[1] as f64, c[2] as f64)
+}
+
+/// Map an MTL entry onto a principled material, or a light if it emits
+///
+/// Transparency or a refracting illumination model makes clear glass of index `Ni`, and a
+/// specular colour outweighing the diffuse one (or illumination model 3) a metal of that colour.
+/// Anything else takes its colour from `map_Kd` or `Kd` and its specular strength from `Ks`.
+/// Roughness follows the Phong exponent, unless the PBR extension's `Pr` and `Pm` give roughness
+/// and metalness.
+fn mtl_material(mtl: &tobj::Material, dir: &Path) -> Result<Arc<dyn Material>, String> {
+    let kd = mtl.diffuse.map(color).unwrap_or(Color::new(0.8, 0.8, 0.8));
+    let ks = mtl.specular.map(color).unwrap_or(Color::zero());
+    let ke = mtl
+        .unknown_param
+        .get("Ke")
+        .map(|ke| {
+            let c: Vec<f64> = ke
+                .split_whitespace()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|err| format!("material {}: bad Ke `{}`: {}", mtl.name, ke, err))?;
+            match c[..] {
+                [r, g, b] => Ok(Color::new(r, g, b)),
+                [x] => Ok(Color::new(x, x, x)),
+                _ => Err(format!("material {}: bad Ke `{}`", mtl.name, ke)),
+            }
+        })
+        .transpose()?
+        .unwrap_or(Color::zero());
+    let max = |c: Color| c.x.max(c.y).max(c.z);
+    let illum = mtl.illumination_model.unwrap_or(2);
+    let param = |name: &str| {
+        mtl.unknown_param
+            .get(name)
+            .map(|value| {
+                value.trim().parse::<f64>().map_err(|err| {
+                    format!("material {}: bad {} `{}`: {}", mtl.name, name, value, err)
+                })
+            })
+            .transpose()
+    };
+
+    if max(ke) > 0.0 {
+        return Ok(Arc::new(DiffuseLight::new(ConstantTexture::new(ke))));
+    }
+    let transparent = mtl.dissolve.is_some_and(|d| d < 1.0) || matches!(illum, 4 | 6 | 7 | 9);
+    let metal = !transparent && (illum == 3 || (illum != 1 && max(ks) > max(kd)));
+    let roughness = match param("Pr")? {
+        Some(roughness) => roughness,
+        None if transparent => 0.0,
+        None => (2.0 / (mtl.shininess.unwrap_or(0.0) as f64 + 2.0)).sqrt(),
+    };
+    let metallic = param("Pm")?.unwrap_or(if metal { 1.0 } else { 0.0 });
+
+    let base_color: Arc<dyn Texture> = if transparent {
+        Arc::new(ConstantTexture::new(Color::ones()))
+    } else if metal {
+        Arc::new(ConstantTexture::new(ks))
+    } else {
+        match &mtl.diffuse_texture {
+            Some(texture) => {
+                let path = dir.join(texture);
+                let image = image::open(&path)
+                    .map_err(|err| format!("Failed to open {}: {}", path.display(), err))?
+                    .to_rgb8();
+                let (width, height) = image.dimensions();
+                Arc::new(ImageTexture::new(image.into_raw(), width, height))
+            }
+            None => Arc::new(ConstantTexture::new(kd)),
+        }
+    };
+    let mut material = Principled::new(base_color)
+        .with_metallic(metallic)
+        .with_roughness(roughness)
+        .with_specular(max(ks).min(1.0));
+    if transparent {
+        material = material
+            .with_transmission(1.0)
+            .with_specular(0.5)
+            .with_ior(mtl.optical_density.unwrap_or(1.5) as f64);
+    }
+    Ok(Arc::new(material))
+}
+
+/// Indexed triangles and the vertex attributes they share
+#[derive(Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    /// Shading normals, empty or one per position
+    pub normals: Vec<Vec3>,
+    /// Texture coordinates, empty or one per position
+    pub uvs: Vec<[f64; 2]>,
+    /// Vertex colours, empty or one per position
+    pub colors: Vec<Color>,
+    pub faces: Vec<[u32; 3]>,
+}
+
+/// Faces meeting at a sharper angle than this keep a hard edge when normals are generated
+pub const DEFAULT_CREASE_ANGLE: f64 = 60.0;
+
+impl MeshData {
+    /// Fill in smooth vertex normals for a mesh that has none
+    ///
+    /// Each corner gets the average of the normals of the faces around its position, weighted by
+    /// the angle they span there, skipping faces that meet its own face at more than
+    /// `crease_angle` degrees. Vertices on a crease are split so both sides keep their normal.
+    pub fn generate_normals(&mut self, crease_angle: f64) {
+        let cos_crease = crease_angle.to_radians().cos();
+        let positions = &self.positions;
+
+        // vertices duplicated for their attributes still share one position
+        let mut welded: HashMap<[u64; 3], usize> = HashMap::new();
+        let weld: Vec<usize> = positions
+            .iter()
+            .map(|p| {
+                let n = welded.len();
+                *welded
+                    .entry([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()])
+                    .or_insert(n)
+            })
+            .collect();
+
+        let mut face_normals = Vec::with_capacity(self.faces.len());
+        let mut corner_angles = Vec::with_capacity(self.faces.len());
+        let mut incident: Vec<Vec<usize>> = vec![Vec::new(); welded.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            let [a, b, c] = face.map(|i| positions[i as usize]);
+            face_normals.push(Vec3::cross(b - a, c - a).unit());
+            let angle = |p: Vec3, q: Vec3, r: Vec3| {
+                let (u, v) = ((q - p).unit(), (r - p).unit());
+                u.dot(v).clamp(-1.0, 1.0).acos()
+            };
+            corner_angles.push([angle(a, b, c), angle(b, c, a), angle(c, a, b)]);
+            for &i in face {
+                incident[weld[i as usize]].push(f);
+            }
+        }
+
+        let mut split: HashMap<(u32, [u64; 3]), u32> = HashMap::new();
+        let mut mesh = MeshData {
+            positions: Vec::with_capacity(positions.len()),
+            normals: Vec::with_capacity(positions.len()),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            faces: Vec::with_capacity(self.faces.len()),
+        };
+        for (f, face) in self.faces.iter().enumerate() {
+            let mut new_face = [0; 3];
+            for (corner, &i) in face.iter().enumerate() {
+                let v = weld[i as usize];
+                let mut normal = Vec3::zero();
+                for &g in &incident[v] {
+                    if face_normals[f].dot(face_normals[g]) < cos_crease {
+                        continue;
+                    }
+                    let k = self.faces[g]
+                        .iter()
+                        .position(|&j| weld[j as usize] == v)
+                        .unwrap();
+                    normal += corner_angles[g][k] * face_normals[g];
+                }
+                let normal = if normal.squared_length() > 0.0 {
+                    normal.unit()
+                } else {
+                    face_normals[f]
+                };
+                let key = (
+                    i,
+                    [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()],
+                );
+                new_face[corner] = *split.entry(key).or_insert_with(|| {
+                    mesh.positions.push(positions[i as usize]);
+                    mesh.normals.push(normal);
+                    if !self.uvs.is_empty() {
+                        mesh.uvs.push(self.uvs[i as usize]);
+                    }
+                    if !self.colors.is_empty() {
+                        mesh.colors.push(self.colors[i as usize]);
+                    }
+                    mesh.positions.len() as u32 - 1
+                });
+            }
+            mesh.faces.push(new_face);
+        }
+        *self = mesh;
+    }
+
+    /// Scale every position by `scale`, then move it by `offset`
+    fn place(&mut self, offset: Vec3, scale: f64) {
+        for p in &mut self.positions {
+            *p = *p * scale + offset;
+        }
+    }
+
+    /// Drop faces whose vertices are collinear, they have no normal to shade with
+    pub fn remove_degenerate_faces(&mut self) {
+        let positions = &self.positions;
+        self.faces.retain(|face| {
+            let [a, b, c] = face.map(|i| positions[i as usize]);
+            Vec3::cross(b - a, c - a).squared_length() > 0.0
+        });
+    }
+}
+
+/// Triangle mesh with one material, storing every vertex once and a BVH over face indices
 pub struct Mesh {
-    tris: HittableList,
+    name: String,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+    colors: Vec<Color>,
+    material: Arc<dyn Material>,
+    /// Tangent-space normal map and the strength of its x and y components
+    normal_map: Option<(Arc<dyn Texture>, f64)>,
+    faces: BVH<[u32; 3]>,
+    /// Running total of the face areas in the order of `faces.primitives()`, to pick faces by
+    /// area when the mesh is sampled as a light
+    areas: Vec<f64>,
 }
 
 impl Mesh {
-    pub fn new<M: Material + Clone + 'static>(
-        positions: Vec<Vec3>,
-        indices: Vec<u32>,
-        material: M,
-    ) -> Mesh {
-        let mut tris = HittableList::default();
+    /// Build the BVH over the faces of `data`, with leaves of up to `leaf_size` faces
+    pub fn new(
+        name: &str,
+        mut data: MeshData,
+        material: Arc<dyn Material>,
+        leaf_size: usize,
+    ) -> Result<Mesh, String> {
+        data.remove_degenerate_faces();
+        if data.faces.is_empty() {
+            return Err(format!("{} has no triangles", name));
+        }
+        let faces = data
+            .faces
+            .iter()
+            .map(|&face| {
+                (
+                    face,
+                    triangle_bbox(&face.map(|i| data.positions[i as usize])),
+                )
+            })
+            .collect();
+        let faces = BVH::from_boxes(faces, leaf_size);
+        let mut total = 0.0;
+        let areas = faces
+            .primitives()
+            .iter()
+            .map(|face| {
+                let [a, b, c] = face.map(|i| data.positions[i as usize]);
+                total += 0.5 * Vec3::cross(b - a, c - a).length();
+                total
+            })
+            .collect();
+        Ok(Mesh {
+            name: name.to_string(),
+            positions: data.positions,
+            normals: data.normals,
+            uvs: data.uvs,
+            colors: data.colors,
+            material,
+            normal_map: None,
+            faces,
+            areas,
+        })
+    }
 
-        for i in 0..indices.len() / 3 {
-            let vertices = [
-                positions[indices[i * 3] as usize],
-                positions[indices[i * 3 + 1] as usize],
-                positions[indices[i * 3 + 2] as usize],
-            ];
-            tris.push(Triangle::new(vertices, material.clone()));
+    /// Perturb the shading normal with a tangent-space normal map, +Y pointing towards
+    /// increasing v; needs texture coordinates
+    pub fn with_normal_map(mut self, map: Arc<dyn Texture>, scale: f64) -> Mesh {
+        if !self.uvs.is_empty() {
+            self.normal_map = Some((map, scale));
         }
+        self
+    }
 
-        Mesh { tris }
+    /// Bend `normal` by the normal map at `(u, v)`, using the tangent frame of face `idx`
+    fn map_normal(&self, idx: [usize; 3], normal: Vec3, u: f64, v: f64, p: Point3) -> Vec3 {
+        let Some((map, scale)) = &self.normal_map else {
+            return normal;
+        };
+        let [p0, p1, p2] = idx.map(|i| self.positions[i]);
+        let [uv0, uv1, uv2] = idx.map(|i| self.uvs[i]);
+        let (dp1, dp2) = (p1 - p0, p2 - p0);
+        let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+        let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+        let det = du1 * dv2 - du2 * dv1;
+        if det == 0.0 {
+            return normal;
+        }
+        let tangent = (dv2 * dp1 - dv1 * dp2) / det;
+        let bitangent = (du1 * dp2 - du2 * dp1) / det;
+        let tangent = tangent - normal.dot(tangent) * normal;
+        if tangent.squared_length() == 0.0 {
+            return normal;
+        }
+        let tangent = tangent.unit();
+        let mut bitangent_n = Vec3::cross(normal, tangent);
+        if bitangent_n.dot(bitangent) < 0.0 {
+            bitangent_n = -bitangent_n;
+        }
+
+        let c = map.texture_map(u, v, &p);
+        let (x, y, z) = (
+            (2.0 * c.x - 1.0) * scale,
+            (2.0 * c.y - 1.0) * scale,
+            2.0 * c.z - 1.0,
+        );
+        let mapped = x * tangent + y * bitangent_n + z * normal;
+        if mapped.squared_length() == 0.0 {
+            normal
+        } else {
+            mapped.unit()
+        }
     }
 
-    pub fn load_obj<'a, P: AsRef<Path>, M: Material + Clone + 'static>(
+    /// Closest crossing of `r` with the faces, which needs no sampler
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.faces.traverse(r, t_min, t_max, |face, t_max| {
+            let idx = face.map(|i| i as usize);
+            let mut rec = hit_triangle(
+                r,
+                t_min,
+                t_max,
+                &idx.map(|i| self.positions[i]),
+                (!self.normals.is_empty()).then(|| idx.map(|i| self.normals[i])),
+                (!self.uvs.is_empty()).then(|| idx.map(|i| self.uvs[i])),
+                (!self.colors.is_empty()).then(|| idx.map(|i| self.colors[i])),
+                &*self.material,
+            )?;
+            rec.normal = self.map_normal(idx, rec.normal, rec.u, rec.v, rec.position);
+            Some(rec)
+        })
+    }
+
+    fn area(&self) -> f64 {
+        self.areas.last().copied().unwrap_or(0.0)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        self.faces.stats()
+    }
+
+    /// Load every model of an OBJ file as its own mesh, scaled by `scale` then moved by `offset`
+    ///
+    /// Vertex normals and texture coordinates are used when the file has them; models without
+    /// normals get generated ones creased at `crease_angle` degrees, or stay faceted if it is
+    /// zero. Models take their material from the MTL library, falling back to `material` for
+    /// models without one.
+    pub fn load_obj<P: AsRef<Path>>(
         path: P,
         offset: Vec3,
         scale: f64,
-        material: M,
-    ) -> Result<Mesh, String> {
-        let models = match tobj::load_obj(path.as_ref(), &tobj::OFFLINE_RENDERING_LOAD_OPTIONS) {
-            Ok((models, _)) => {
-                let m = &models[0];
-                println!("Loading model {}", m.name);
-                let mesh = &m.mesh;
-                println!("{} has {} triangles", m.name, mesh.indices.len() / 3);
-
-                let tri_positions = mesh
+        material: Option<Arc<dyn Material>>,
+        crease_angle: f64,
+        leaf_size: usize,
+    ) -> Result<Vec<Mesh>, String> {
+        let path = path.as_ref();
+        let options = tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+        };
+        let (models, mtls) = tobj::load_obj(path, &options)
+            .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?;
+        let mtls = mtls.unwrap_or_else(|err| {
+            println!("No materials for {}: {}", path.display(), err);
+            Vec::new()
+        });
+        let dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(PathBuf::new);
+        let materials = mtls
+            .iter()
+            .map(|mtl| mtl_material(mtl, &dir))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut meshes = Vec::with_capacity(models.len());
+        for m in models {
+            let mesh = m.mesh;
+            println!(
+                "Loading model {} with {} triangles",
+                m.name,
+                mesh.indices.len() / 3
+            );
+            let material = match mesh.material_id.and_then(|id| materials.get(id)) {
+                Some(material) => material.clone(),
+                None => material.clone().ok_or_else(|| {
+                    format!("{}: model {} has no material", path.display(), m.name)
+                })?,
+            };
+
+            let mut data = MeshData {
+                positions: mesh
                     .positions
-                    .chunks(3)
+                    .chunks_exact(3)
                     .map(|p| Point3::new(p[0] as f64, p[1] as f64, p[2] as f64) * scale + offset)
-                    .collect();
-
-                let tri_indices = &mesh.indices;
+                    .collect(),
+                normals: mesh
+                    .normals
+                    .chunks_exact(3)
+                    .map(|n| Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64))
+                    .collect(),
+                uvs: mesh
+                    .texcoords
+                    .chunks_exact(2)
+                    .map(|uv| [uv[0] as f64, uv[1] as f64])
+                    .collect(),
+                colors: Vec::new(),
+                faces: mesh
+                    .indices
+                    .chunks_exact(3)
+                    .map(|f| [f[0], f[1], f[2]])
+                    .collect(),
+            };
+            if data.normals.is_empty() && crease_angle > 0.0 {
+                data.remove_degenerate_faces();
+                data.generate_normals(crease_angle);
+            }
+            let name = format!("{}:{}", path.display(), m.name);
+            meshes.push(Mesh::new(&name, data, material, leaf_size)?);
+        }
+        if meshes.is_empty() {
+            return Err(format!("{}: no models", path.display()));
+        }
+        Ok(meshes)
+    }
 
-                Mesh::new(tri_positions, tri_indices.to_vec(), material)
+    /// Load an ASCII or binary PLY file, scaled by `scale` then moved by `offset`
+    ///
+    /// Normals are generated as for [`Mesh::load_obj`] when the file has none. Without a
+    /// `material` the mesh is Lambertian, coloured by its vertex colours.
+    pub fn load_ply<P: AsRef<Path>>(
+        path: P,
+        offset: Vec3,
+        scale: f64,
+        material: Option<Arc<dyn Material>>,
+        crease_angle: f64,
+        leaf_size: usize,
+    ) -> Result<Mesh, String> {
+        let path = path.as_ref();
+        let mut data = read_ply(path)?;
+        println!(
+            "Loading {} with {} triangles",
+            path.display(),
+            data.faces.len()
+        );
+        data.place(offset, scale);
+        if data.normals.len() != data.positions.len() {
+            data.normals.clear();
+        }
+        if data.uvs.len() != data.positions.len() {
+            data.uvs.clear();
+        }
+        if data.colors.len() != data.positions.len() {
+            data.colors.clear();
+        }
+        if data.normals.is_empty() && crease_angle > 0.0 {
+            data.remove_degenerate_faces();
+            data.generate_normals(crease_angle);
+        }
+        let material = match material {
+            Some(material) => material,
+            None if !data.colors.is_empty() => Arc::new(Lambertian::new(VertexColorTexture)),
+            None => {
+                return Err(format!(
+                    "{}: no vertex colours to use without a material",
+                    path.display()
+                ))
             }
-            Err(err) => return Err(format!("Failed to load obj: {}", err)),
         };
-        Ok(models)
+        Mesh::new(&path.display().to_string(), data, material, leaf_size)
+    }
+
+    /// Load an ASCII or binary STL file, scaled by `scale` then moved by `offset`
+    ///
+    /// STL only stores facet normals, so vertex normals are generated creased at `crease_angle`
+    /// degrees, or the mesh stays faceted if it is zero.
+    pub fn load_stl<P: AsRef<Path>>(
+        path: P,
+        offset: Vec3,
+        scale: f64,
+        material: Arc<dyn Material>,
+        crease_angle: f64,
+        leaf_size: usize,
+    ) -> Result<Mesh, String> {
+        let path = path.as_ref();
+        let stl = File::open(path)
+            .and_then(|file| stl_io::read_stl(&mut BufReader::new(file)))
+            .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?;
+        println!(
+            "Loading {} with {} triangles",
+            path.display(),
+            stl.faces.len()
+        );
+        let mut data = MeshData {
+            positions: stl
+                .vertices
+                .iter()
+                .map(|v| Point3::new(v[0] as f64, v[1] as f64, v[2] as f64))
+                .collect(),
+            faces: stl
+                .faces
+                .iter()
+                .map(|f| f.vertices.map(|i| i as u32))
+                .collect(),
+            ..MeshData::default()
+        };
+        data.place(offset, scale);
+        if crease_angle > 0.0 {
+            data.remove_degenerate_faces();
+            data.generate_normals(crease_angle);
+        }
+        Mesh::new(&path.display().to_string(), data, material, leaf_size)
     }
 }
 
 impl Hittable for Mesh {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.tris.hit(r, t_min, t_max)
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        self.intersect(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.faces.bbox())
+    }
+
+    /// Sums over every face `v` crosses, as a point sampled behind the first one still lies
+    /// in that direction
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        let r = Ray::new(o, v, 0.0);
+        let area = self.area();
+        let mut pdf = 0.0;
+        self.faces.visit(&r, 0.001, f64::INFINITY, |face| {
+            let vertices = face.map(|i| self.positions[i as usize]);
+            let hit = hit_triangle(
+                &r,
+                0.001,
+                f64::INFINITY,
+                &vertices,
+                None,
+                None,
+                None,
+                &*self.material,
+            );
+            if let Some(rec) = hit {
+                let cos = v.dot(rec.normal).abs() / v.length();
+                if cos != 0.0 {
+                    pdf += rec.t.powi(2) * v.squared_length() / (cos * area);
+                }
+            }
+            true
+        });
+        pdf
+    }
+
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        match self.sample_surface(rng) {
+            Some((rec, _)) => rec.position - o,
+            None => Vec3::new(1.0, 0.0, 0.0),
+        }
     }
 
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        self.tris.bounding_box(t0, t1)
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        let area = self.area();
+        if area == 0.0 {
+            return None;
+        }
+        let target = rng.gen::<f64>() * area;
+        let f = self
+            .areas
+            .partition_point(|&a| a <= target)
+            .min(self.areas.len() - 1);
+        let idx = self.faces.primitives()[f].map(|i| i as usize);
+        let [p0, p1, p2] = idx.map(|i| self.positions[i]);
+        // fold the unit square onto the triangle for uniform barycentrics
+        let (mut b1, mut b2) = (rng.gen::<f64>(), rng.gen::<f64>());
+        if b1 + b2 > 1.0 {
+            (b1, b2) = (1.0 - b1, 1.0 - b2);
+        }
+        let b0 = 1.0 - b1 - b2;
+        let (u, v) = if self.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let [uv0, uv1, uv2] = idx.map(|i| self.uvs[i]);
+            (
+                b0 * uv0[0] + b1 * uv1[0] + b2 * uv2[0],
+                b0 * uv0[1] + b1 * uv1[1] + b2 * uv2[1],
+            )
+        };
+        let rec = HitRecord {
+            position: b0 * p0 + b1 * p1 + b2 * p2,
+            normal: Vec3::cross(p1 - p0, p2 - p0).unit(),
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            material: &*self.material,
+            color: (!self.colors.is_empty()).then(|| {
+                let [c0, c1, c2] = idx.map(|i| self.colors[i]);
+                b0 * c0 + b1 * c1 + b2 * c2
+            }),
+        };
+        Some((rec, 1.0 / area))
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        match self.intersect(r, 0.00001, f64::INFINITY) {
+            Some(_) => 1.0 / self.area(),
+            None => 0.0,
+        }
     }
 }
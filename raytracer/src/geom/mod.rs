@@ -1,5 +1,7 @@
 pub mod cube;
+pub mod medium;
 pub mod mesh;
+pub mod ply;
 pub mod quad;
 pub mod ray;
 pub mod sphere;
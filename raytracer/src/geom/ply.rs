@@ -0,0 +1,338 @@
+use std::{fs, path::Path};
+
+use crate::geom::{mesh::MeshData, vec3::Vec3};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type `{}`", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+enum Property {
+    Scalar(Scalar),
+    List(Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+/// The body of the file, read value by value
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.next().ok_or("unexpected end of file")?;
+            return token
+                .parse()
+                .map_err(|_| format!("invalid number `{}`", token));
+        }
+        let size = scalar.size();
+        if self.bytes.len() < size {
+            return Err("unexpected end of file".to_string());
+        }
+        let (value, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(value);
+        if self.format == Format::BigEndian {
+            buf[..size].reverse();
+        }
+        let [a, b, c, d, ..] = buf;
+        Ok(match scalar {
+            Scalar::I8 => a as i8 as f64,
+            Scalar::U8 => a as f64,
+            Scalar::I16 => i16::from_le_bytes([a, b]) as f64,
+            Scalar::U16 => u16::from_le_bytes([a, b]) as f64,
+            Scalar::I32 => i32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::U32 => u32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::F32 => f32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::F64 => f64::from_le_bytes(buf),
+        })
+    }
+
+    fn read_list(&mut self, count: Scalar, item: Scalar) -> Result<Vec<f64>, String> {
+        let n = self.read(count)? as usize;
+        (0..n).map(|_| self.read(item)).collect()
+    }
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), String> {
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", kind, _] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(format!("unknown format `{}`", kind)),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count `{}`", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or("property outside of an element")?
+                .properties
+                .push((
+                    name.to_string(),
+                    Property::List(Scalar::parse(count)?, Scalar::parse(item)?),
+                )),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or("property outside of an element")?
+                .properties
+                .push((name.to_string(), Property::Scalar(Scalar::parse(kind)?))),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("unexpected header line `{}`", line)),
+        }
+    }
+    Ok((format.ok_or("missing format")?, elements))
+}
+
+/// Scale colour channels stored as integers to `[0, 1]`
+fn channel_scale(property: &Property) -> f64 {
+    match property {
+        Property::Scalar(Scalar::U8) => 1.0 / 255.0,
+        Property::Scalar(Scalar::U16) => 1.0 / 65535.0,
+        _ => 1.0,
+    }
+}
+
+/// Read the vertices and faces of an ASCII or binary PLY file
+///
+/// Positions, normals, texture coordinates and colours are taken from the `vertex` element and
+/// polygons from the `face` element's `vertex_indices`, split into triangle fans. Other elements
+/// and properties are skipped.
+pub fn read_ply(path: &Path) -> Result<MeshData, String> {
+    let bytes =
+        fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let error = |err: String| format!("{}: {}", path.display(), err);
+    let end = b"end_header";
+    let header_len = bytes
+        .windows(end.len())
+        .position(|w| w == end)
+        .ok_or_else(|| error("missing end_header".to_string()))?;
+    let body_start = bytes[header_len..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| header_len + i + 1);
+    let header = String::from_utf8_lossy(&bytes[..header_len]);
+    let (format, elements) = parse_header(&header).map_err(error)?;
+
+    let body = &bytes[body_start..];
+    let text = match format {
+        Format::Ascii => std::str::from_utf8(body).map_err(|err| error(err.to_string()))?,
+        _ => "",
+    };
+    let mut body = Body {
+        format,
+        bytes: body,
+        tokens: text.split_ascii_whitespace(),
+    };
+
+    let mut data = MeshData::default();
+    for element in &elements {
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|(name, _)| names.contains(&name.as_str()))
+        };
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let uv = [
+            find(&["u", "s", "texture_u", "texture_s"]),
+            find(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let color = [
+            find(&["red", "r", "diffuse_red"]),
+            find(&["green", "g", "diffuse_green"]),
+            find(&["blue", "b", "diffuse_blue"]),
+        ];
+        let indices = find(&["vertex_indices", "vertex_index"]);
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            let mut list = Vec::new();
+            for (k, (_, property)) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar(scalar) => values[k] = body.read(*scalar).map_err(error)?,
+                    Property::List(count, item) => {
+                        let items = body.read_list(*count, *item).map_err(error)?;
+                        if Some(k) == indices {
+                            list = items;
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let get = |idx: [Option<usize>; 3]| {
+                        let [x, y, z] = idx.map(|i| i.map(|i| values[i]));
+                        Some(Vec3::new(x?, y?, z?))
+                    };
+                    data.positions.push(
+                        get(position).ok_or_else(|| error("vertex without position".into()))?,
+                    );
+                    if let Some(n) = get(normal) {
+                        data.normals.push(n);
+                    }
+                    if let [Some(u), Some(v)] = uv {
+                        data.uvs.push([values[u], values[v]]);
+                    }
+                    if let Some(c) = get(color) {
+                        let scale = channel_scale(&element.properties[color[0].unwrap()].1);
+                        data.colors.push(c * scale);
+                    }
+                }
+                "face" => {
+                    let list = list
+                        .into_iter()
+                        .map(|i| {
+                            if i >= 0.0 && i.fract() == 0.0 && i <= u32::MAX as f64 {
+                                Ok(i as u32)
+                            } else {
+                                Err(error(format!("invalid vertex index {}", i)))
+                            }
+                        })
+                        .collect::<Result<Vec<u32>, String>>()?;
+                    for k in 2..list.len() {
+                        data.faces.push([list[0], list[k - 1], list[k]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let n = data.positions.len();
+    if let Some(&face) = data
+        .faces
+        .iter()
+        .find(|face| face.iter().any(|&i| i as usize >= n))
+    {
+        return Err(error(format!("face {:?} indexes past the vertices", face)));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::render::color::Color;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    /// The unit quad every `quad_*.ply` fixture stores
+    fn check_quad(data: &MeshData) {
+        assert_eq!(
+            data.positions,
+            [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ]
+        );
+        assert_eq!(data.normals, [Vec3::new(0.0, 0.0, 1.0); 4]);
+        assert_eq!(data.uvs, [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        assert_eq!(
+            data.colors,
+            [
+                Color::new(1.0, 0.0, 0.0),
+                Color::new(0.0, 1.0, 0.0),
+                Color::new(0.0, 0.0, 1.0),
+                Color::new(1.0, 1.0, 1.0),
+            ]
+        );
+        assert_eq!(data.faces, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn reads_ascii() {
+        check_quad(&read_ply(&fixture("quad_ascii.ply")).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_little_endian() {
+        check_quad(&read_ply(&fixture("quad_binary_le.ply")).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_big_endian() {
+        check_quad(&read_ply(&fixture("quad_binary_be.ply")).unwrap());
+    }
+
+    #[test]
+    fn rejects_negative_indices() {
+        let Err(err) = read_ply(&fixture("bad_index.ply")) else {
+            panic!("bad_index.ply was read");
+        };
+        assert!(err.ends_with("invalid vertex index -1"), "{}", err);
+    }
+}
@@ -3,6 +3,7 @@ use rand::Rng;
 use crate::hit::aabb::AABB;
 use crate::hit::hittable::{HitRecord, Hittable};
 use crate::render::mat::Material;
+use crate::render::sampler::Sampler;
 
 use super::ray::Ray;
 use super::vec3::{Point3, Vec3};
@@ -47,10 +48,13 @@ impl<M: Material> Quad<M> {
             material,
         }
     }
-}
 
-impl<M: Material> Hittable for Quad<M> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn area(&self) -> f64 {
+        (self.a1 - self.a0) * (self.b1 - self.b0)
+    }
+
+    /// Crossing of `r` with the quad, which needs no sampler
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let (k_axis_index, a_axis_index, b_axis_index) = self.plane.get_axis_index();
 
         let t = (self.k - r.origin().get(k_axis_index)) / r.direction().get(k_axis_index);
@@ -76,6 +80,7 @@ impl<M: Material> Hittable for Quad<M> {
                     v,
                     front_face: false,
                     material: &self.material,
+                    color: None,
                 };
 
                 rec.set_face_normal(r, normal);
@@ -84,19 +89,32 @@ impl<M: Material> Hittable for Quad<M> {
             }
         }
     }
+}
+
+impl<M: Material> Hittable for Quad<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        self.intersect(r, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
         // Pad to avoid NaNs
         let delta = 1e-4;
-        let min = Vec3::new(self.a0, self.b0, self.k - delta);
-        let max = Vec3::new(self.a1, self.b1, self.k + delta);
+        let (k_axis, a_axis, b_axis) = self.plane.get_axis_index();
+        let mut min = Vec3::zero();
+        let mut max = Vec3::zero();
+        min.set(a_axis, self.a0);
+        min.set(b_axis, self.b0);
+        min.set(k_axis, self.k - delta);
+        max.set(a_axis, self.a1);
+        max.set(b_axis, self.b1);
+        max.set(k_axis, self.k + delta);
 
         Some(AABB::new(min, max))
     }
 
     fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
-        if let Some(rec) = self.hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY) {
-            let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+        if let Some(rec) = self.intersect(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY) {
+            let area = self.area();
             let distance_squared = rec.t.powi(2) * v.length().powi(2);
             let cos = v.dot(rec.normal).abs() / v.length();
             if cos != 0.0 {
@@ -109,8 +127,7 @@ impl<M: Material> Hittable for Quad<M> {
         }
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
-        let mut rng = rand::thread_rng();
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
         let (k_axis, a_axis, b_axis) = self.plane.get_axis_index();
         let mut random_point = Vec3::zero();
         random_point.set(a_axis, rng.gen_range(self.a0..self.a1));
@@ -118,4 +135,33 @@ impl<M: Material> Hittable for Quad<M> {
         random_point.set(k_axis, self.k);
         random_point - o
     }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        let (k_axis, a_axis, b_axis) = self.plane.get_axis_index();
+        let (u, v) = (rng.gen::<f64>(), rng.gen::<f64>());
+        let mut position = Vec3::zero();
+        position.set(a_axis, self.a0 + u * (self.a1 - self.a0));
+        position.set(b_axis, self.b0 + v * (self.b1 - self.b0));
+        position.set(k_axis, self.k);
+        let mut normal = Vec3::zero();
+        normal.set(k_axis, 1.0);
+        let rec = HitRecord {
+            position,
+            normal,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            material: &self.material,
+            color: None,
+        };
+        Some((rec, 1.0 / self.area()))
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        match self.intersect(r, 0.00001, f64::INFINITY) {
+            Some(_) => 1.0 / self.area(),
+            None => 0.0,
+        }
+    }
 }
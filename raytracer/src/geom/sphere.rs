@@ -1,9 +1,10 @@
-﻿use crate::geom::ray::Ray;
+use crate::geom::ray::Ray;
 use crate::geom::vec3::{Point3, Vec3};
 use crate::hit::aabb::{self, AABB};
 use crate::hit::hittable::{HitRecord, Hittable};
 use crate::render::mat::Material;
 use crate::render::onb::ONB;
+use crate::render::sampler::Sampler;
 use crate::utils::PI;
 
 pub struct Sphere<M: Material> {
@@ -20,14 +21,9 @@ impl<M: Material> Sphere<M> {
             material,
         }
     }
-}
-
-pub fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
-    ((p.z.atan2(p.x) + PI) / (2.0 * PI), p.y.acos() / PI)
-}
 
-impl<M: Material> Hittable for Sphere<M> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    /// Nearest crossing of `r` with the sphere, which needs no sampler
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin() - self.center;
         let a = r.direction().length().powi(2);
         let half_b = oc.dot(r.direction());
@@ -55,6 +51,7 @@ impl<M: Material> Hittable for Sphere<M> {
             v: 0.0,
             front_face: false,
             material: &self.material,
+            color: None,
         };
 
         let outward_normal = (rec.position - self.center) / self.radius;
@@ -66,6 +63,16 @@ impl<M: Material> Hittable for Sphere<M> {
 
         Some(rec)
     }
+}
+
+pub fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
+    ((p.z.atan2(p.x) + PI) / (2.0 * PI), p.y.acos() / PI)
+}
+
+impl<M: Material> Hittable for Sphere<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        self.intersect(r, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
         let min = self.center - Vec3::new(self.radius, self.radius, self.radius);
@@ -75,7 +82,10 @@ impl<M: Material> Hittable for Sphere<M> {
     }
 
     fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
-        if let Some(_) = self.hit(&Ray::new(o, v, 0.0), 0.001, f64::MAX) {
+        if self
+            .intersect(&Ray::new(o, v, 0.0), 0.001, f64::MAX)
+            .is_some()
+        {
             let cos_theta_max =
                 (1.0 - self.radius.powi(2) / (self.center - o).length().powi(2)).sqrt();
             let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
@@ -85,11 +95,34 @@ impl<M: Material> Hittable for Sphere<M> {
         }
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
         let direction = self.center - o;
         let distance_squared = direction.length().powi(2);
         let uvw = ONB::build_from_w(&direction);
-        uvw.local(&Vec3::random_to_sphere(self.radius, distance_squared))
+        uvw.local(&Vec3::random_to_sphere(self.radius, distance_squared, rng))
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        let normal = Vec3::random_unit_vector(rng);
+        let (u, v) = get_sphere_uv(&normal);
+        let rec = HitRecord {
+            position: self.center + self.radius * normal,
+            normal,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            material: &self.material,
+            color: None,
+        };
+        Some((rec, 1.0 / (4.0 * PI * self.radius.powi(2))))
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        match self.intersect(r, 0.00001, f64::INFINITY) {
+            Some(_) => 1.0 / (4.0 * PI * self.radius.powi(2)),
+            None => 0.0,
+        }
     }
 }
 
@@ -129,7 +162,7 @@ impl<M: Material> MovingSphere<M> {
 }
 
 impl<M: Material> Hittable for MovingSphere<M> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Sampler) -> Option<HitRecord<'_>> {
         let oc = r.origin() - self.center(r.time());
         let a = r.direction().length().powi(2);
         let half_b = oc.dot(r.direction());
@@ -157,6 +190,7 @@ impl<M: Material> Hittable for MovingSphere<M> {
             v: 0.0,
             front_face: false,
             material: &self.material,
+            color: None,
         };
 
         let outward_normal = (rec.position - self.center(r.time())) / self.radius;
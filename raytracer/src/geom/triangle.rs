@@ -2,77 +2,141 @@ use crate::geom::ray::Ray;
 use crate::geom::vec3::{Point3, Vec3};
 use crate::hit::aabb::AABB;
 use crate::hit::hittable::{HitRecord, Hittable};
+use crate::render::color::Color;
 use crate::render::mat::Material;
+use crate::render::sampler::Sampler;
 pub struct Triangle<M: Material> {
     vertices: [Point3; 3],
+    /// Per-vertex shading normals, interpolated across the face
+    normals: Option<[Vec3; 3]>,
+    /// Per-vertex texture coordinates, the barycentrics are used without them
+    uvs: Option<[[f64; 2]; 3]>,
     material: M,
 }
 
 impl<M: Material> Triangle<M> {
     pub fn new(vertices: [Point3; 3], material: M) -> Triangle<M> {
-        Triangle { vertices, material }
+        Triangle::with_attributes(vertices, None, None, material)
+    }
+
+    pub fn with_attributes(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+        material: M,
+    ) -> Triangle<M> {
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            material,
+        }
     }
 }
 
-impl<M: Material> Hittable for Triangle<M> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        // Möller–Trumbore algorithm
-        let s = r.origin() - self.vertices[0];
-        let e1 = self.vertices[1] - self.vertices[0];
-        let e2 = self.vertices[2] - self.vertices[0];
-        let s1 = Vec3::cross(r.direction(), e2);
-        let s2 = Vec3::cross(s, e1);
-        let s1_e1 = s1.dot(e1);
-        let t = s2.dot(e2) / s1_e1;
-        let b1 = s1.dot(s) / s1_e1;
-        let b2 = s2.dot(r.direction()) / s1_e1;
+/// Intersect `r` with a triangle, interpolating shading normals, texture coordinates and vertex
+/// colours if given
+#[allow(clippy::too_many_arguments)]
+pub fn hit_triangle<'a>(
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    vertices: &[Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[[f64; 2]; 3]>,
+    colors: Option<[Color; 3]>,
+    material: &'a dyn Material,
+) -> Option<HitRecord<'a>> {
+    // Möller–Trumbore algorithm
+    let s = r.origin() - vertices[0];
+    let e1 = vertices[1] - vertices[0];
+    let e2 = vertices[2] - vertices[0];
+    let s1 = Vec3::cross(r.direction(), e2);
+    let s2 = Vec3::cross(s, e1);
+    let s1_e1 = s1.dot(e1);
+    if s1_e1 == 0.0 {
+        // parallel to the plane, or a degenerate triangle
+        return None;
+    }
+    let t = s2.dot(e2) / s1_e1;
+    let b1 = s1.dot(s) / s1_e1;
+    let b2 = s2.dot(r.direction()) / s1_e1;
 
-        if t < t_min || t > t_max {
+    if t < t_min || t > t_max {
+        None
+    } else {
+        if b1 < 0.0 || b2 < 0.0 || (1.0 - b1 - b2) < 0.0 {
             None
         } else {
-            if b1 < 0.0 || b2 < 0.0 || (1.0 - b1 - b2) < 0.0 {
-                None
-            } else {
-                let p = r.at(t);
-                let normal = Vec3::cross(e1, e2).unit();
-                let mut rec = HitRecord {
-                    position: p,
-                    normal,
-                    t,
-                    u: b1,
-                    v: b2,
-                    front_face: false,
-                    material: &self.material,
-                };
-                rec.set_face_normal(r, normal);
-                Some(rec)
+            let p = r.at(t);
+            let b0 = 1.0 - b1 - b2;
+            let normal = Vec3::cross(e1, e2).unit();
+            let (u, v) = match uvs {
+                Some([uv0, uv1, uv2]) => (
+                    b0 * uv0[0] + b1 * uv1[0] + b2 * uv2[0],
+                    b0 * uv0[1] + b1 * uv1[1] + b2 * uv2[1],
+                ),
+                None => (b1, b2),
+            };
+            let mut rec = HitRecord {
+                position: p,
+                normal,
+                t,
+                u,
+                v,
+                front_face: false,
+                material,
+                color: colors.map(|[c0, c1, c2]| b0 * c0 + b1 * c1 + b2 * c2),
+            };
+            rec.set_face_normal(r, normal);
+            if let Some([n0, n1, n2]) = normals {
+                // the face decides which side was hit, the shading normal only bends it
+                let shading = b0 * n0 + b1 * n1 + b2 * n2;
+                if shading.squared_length() > 0.0 {
+                    let shading = shading.unit();
+                    rec.normal = if shading.dot(rec.normal) < 0.0 {
+                        -shading
+                    } else {
+                        shading
+                    };
+                }
             }
+            Some(rec)
         }
     }
+}
 
-    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
-        let min_x = self.vertices[0]
-            .x
-            .min(f64::min(self.vertices[1].x, self.vertices[2].x));
-        let min_y = self.vertices[0]
-            .y
-            .min(f64::min(self.vertices[1].y, self.vertices[2].y));
-        let min_z = self.vertices[0]
-            .z
-            .min(f64::min(self.vertices[1].z, self.vertices[2].z));
-        let max_x = self.vertices[0]
-            .x
-            .max(f64::max(self.vertices[1].x, self.vertices[2].x));
-        let max_y = self.vertices[0]
-            .y
-            .max(f64::max(self.vertices[1].y, self.vertices[2].y));
-        let max_z = self.vertices[0]
-            .z
-            .max(f64::max(self.vertices[1].z, self.vertices[2].z));
+pub fn triangle_bbox(vertices: &[Point3; 3]) -> AABB {
+    let min_x = vertices[0].x.min(f64::min(vertices[1].x, vertices[2].x));
+    let min_y = vertices[0].y.min(f64::min(vertices[1].y, vertices[2].y));
+    let min_z = vertices[0].z.min(f64::min(vertices[1].z, vertices[2].z));
+    let max_x = vertices[0].x.max(f64::max(vertices[1].x, vertices[2].x));
+    let max_y = vertices[0].y.max(f64::max(vertices[1].y, vertices[2].y));
+    let max_z = vertices[0].z.max(f64::max(vertices[1].z, vertices[2].z));
 
-        let min = Vec3::new(min_x, min_y, min_z);
-        let max = Vec3::new(max_x, max_y, max_z);
+    // Pad so that axis-aligned triangles do not get a flat box the slab test misses
+    let delta = Vec3::new(1e-4, 1e-4, 1e-4);
+    let min = Vec3::new(min_x, min_y, min_z) - delta;
+    let max = Vec3::new(max_x, max_y, max_z) + delta;
 
-        Some(AABB::new(min, max))
+    AABB::new(min, max)
+}
+
+impl<M: Material> Hittable for Triangle<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        hit_triangle(
+            r,
+            t_min,
+            t_max,
+            &self.vertices,
+            self.normals,
+            self.uvs,
+            None,
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
+        Some(triangle_bbox(&self.vertices))
     }
 }
@@ -15,8 +15,7 @@ use rand::Rng;
 pub type Point3 = Vec3;
 
 impl Vec3 {
-    pub fn random_range(range: std::ops::Range<f64>) -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_range<R: Rng + ?Sized>(rng: &mut R, range: std::ops::Range<f64>) -> Vec3 {
         let x = rng.gen_range(range.clone());
         let y = rng.gen_range(range.clone());
         let z = rng.gen_range(range);
@@ -86,25 +85,24 @@ impl Vec3 {
         *self / self.length()
     }
 
-    pub fn random() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         let x = rng.gen::<f64>();
         let y = rng.gen::<f64>();
         let z = rng.gen::<f64>();
         Vec3::new(x, y, z)
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         loop {
-            let p = Vec3::random_range(-1.0..1.0);
+            let p = Vec3::random_range(rng, -1.0..1.0);
             if p.squared_length() < 1.0 {
                 return p;
             }
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::random_in_unit_sphere().unit()
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
+        Vec3::random_in_unit_sphere(rng).unit()
     }
 
     pub fn near_zero(&self) -> bool {
@@ -123,8 +121,7 @@ impl Vec3 {
         r_out_perp + r_out_parallel
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         loop {
             let p = Vec3::new(rng.gen_range(-1.0..1.0), 0.0, rng.gen_range(-1.0..1.0));
             if p.squared_length() < 1.0 {
@@ -133,9 +130,7 @@ impl Vec3 {
         }
     }
 
-    pub fn random_cos_direction() -> Vec3 {
-        let mut rng = rand::thread_rng();
-
+    pub fn random_cos_direction<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         let (r1, r2) = (rng.gen::<f64>(), rng.gen::<f64>());
         let phi = 2.0 * PI * r1;
 
@@ -146,8 +141,11 @@ impl Vec3 {
         Vec3::new(x, y, z)
     }
 
-    pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_to_sphere<R: Rng + ?Sized>(
+        radius: f64,
+        distance_squared: f64,
+        rng: &mut R,
+    ) -> Vec3 {
         let (r1, r2) = (rng.gen::<f64>(), rng.gen::<f64>());
         let z = 1.0 + r2 * ((1.0 - radius.powi(2) / distance_squared).sqrt() - 1.0);
         let phi = 2.0 * PI * r1;
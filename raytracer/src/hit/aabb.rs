@@ -1,4 +1,3 @@
-use crate::geom::ray::Ray;
 use crate::geom::vec3::Vec3;
 use std::f64;
 
@@ -13,11 +12,21 @@ impl AABB {
         AABB { min, max }
     }
 
-    pub fn hit(&self, r: &Ray, mut t_in: f64, mut t_out: f64) -> bool {
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Slab test against a ray given by its origin and the reciprocal of its direction
+    pub fn hit_inv(&self, origin: Vec3, inv_dir: Vec3, mut t_in: f64, mut t_out: f64) -> bool {
         for idx in 0..3 {
-            let inv_d = 1.0 / r.direction().get(idx);
-            let t0 = (self.min.get(idx) - r.origin().get(idx)) * inv_d;
-            let t1 = (self.max.get(idx) - r.origin().get(idx)) * inv_d;
+            let inv_d = inv_dir.get(idx);
+            let t0 = (self.min.get(idx) - origin.get(idx)) * inv_d;
+            let t1 = (self.max.get(idx) - origin.get(idx)) * inv_d;
             let (t0, t1) = if inv_d < 0.0 { (t1, t0) } else { (t0, t1) };
             (t_in, t_out) = (t_in.max(t0), t_out.min(t1));
             if t_out <= t_in {
@@ -1,115 +1,517 @@
+use std::fmt;
+
 use crate::geom::ray::Ray;
+use crate::geom::vec3::Vec3;
 use crate::hit::aabb::{surrounding_box, AABB};
 use crate::hit::hittable::{HitRecord, Hittable};
-use std::cmp::Ordering;
+use crate::render::sampler::Sampler;
+
+/// Most primitives a leaf holds unless told otherwise
+pub const DEFAULT_LEAF_SIZE: usize = 4;
+
+/// Number of buckets the centroids are binned into along each axis when searching a split
+const SAH_BINS: usize = 12;
+/// Cost of visiting a node, relative to intersecting one primitive
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+/// Traversal stack depth, and so the deepest a tree may grow before nodes become leaves
+const STACK_SIZE: usize = 64;
+
+#[derive(Copy, Clone)]
+enum Node {
+    /// The first child directly follows its parent in the node array
+    Branch {
+        second: u32,
+        axis: u8,
+    },
+    Leaf {
+        first: u32,
+        count: u32,
+    },
+}
 
-enum BVHNode {
-    Branch { left: Box<BVH>, right: Box<BVH> },
-    Leaf(Box<dyn Hittable>),
+#[derive(Copy, Clone)]
+struct LinearNode {
+    bbox: AABB,
+    node: Node,
+}
+
+/// Bounding volume hierarchy laid out depth first in a single array
+///
+/// Primitives are reordered so that every leaf owns a contiguous range of them. They are boxed
+/// hittables by default; meshes store compact faces instead and intersect them themselves.
+pub struct BVH<P = Box<dyn Hittable>> {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<P>,
 }
 
-pub struct BVH {
-    tree: BVHNode,
+/// A primitive waiting to be placed in the tree
+struct Item<P> {
+    primitive: P,
     bbox: AABB,
+    centroid: Vec3,
+}
+
+fn bounds<'a>(boxes: impl Iterator<Item = &'a AABB>) -> Option<AABB> {
+    boxes.copied().reduce(|a, b| surrounding_box(&a, &b))
 }
 
 impl BVH {
-    pub fn new(mut hit: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> BVH {
-        fn box_compare(
-            time0: f64,
-            time1: f64,
-            axis: usize,
-        ) -> impl FnMut(&Box<dyn Hittable>, &Box<dyn Hittable>) -> Ordering {
-            move |a, b| {
-                let a_bbox = a.bounding_box(time0, time1);
-                let b_bbox = b.bounding_box(time0, time1);
-                if let (Some(a), Some(b)) = (a_bbox, b_bbox) {
-                    let ac = a.min.get(axis) + a.max.get(axis);
-                    let bc = b.min.get(axis) + b.max.get(axis);
-                    ac.partial_cmp(&bc).unwrap()
-                } else {
-                    panic!("no bounding box in bvh node")
+    /// Build the tree over hittables, bounding them over `[time0, time1]`
+    ///
+    /// Leaves hold at most `leaf_size` hittables, fewer where splitting is cheaper.
+    pub fn with_leaf_size(
+        hit: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        leaf_size: usize,
+    ) -> BVH {
+        let primitives = hit
+            .into_iter()
+            .map(|hittable| {
+                let bbox = hittable
+                    .bounding_box(time0, time1)
+                    .expect("no bounding box in bvh node");
+                (hittable, bbox)
+            })
+            .collect();
+        BVH::from_boxes(primitives, leaf_size)
+    }
+}
+
+impl<P> BVH<P> {
+    /// Build the tree with the surface area heuristic, evaluated over binned centroids
+    ///
+    /// Leaves hold at most `leaf_size` primitives, fewer where splitting is cheaper.
+    pub fn from_boxes(primitives: Vec<(P, AABB)>, leaf_size: usize) -> BVH<P> {
+        if primitives.is_empty() {
+            panic!("no object in the scene");
+        }
+        let items = primitives
+            .into_iter()
+            .map(|(primitive, bbox)| Item {
+                primitive,
+                bbox,
+                centroid: bbox.centroid(),
+            })
+            .collect();
+        let mut bvh = BVH {
+            nodes: Vec::new(),
+            primitives: Vec::new(),
+        };
+        bvh.build(items, leaf_size.max(1), 1);
+        bvh
+    }
+
+    fn leaf(&mut self, items: Vec<Item<P>>, bbox: AABB) {
+        self.nodes.push(LinearNode {
+            bbox,
+            node: Node::Leaf {
+                first: self.primitives.len() as u32,
+                count: items.len() as u32,
+            },
+        });
+        self.primitives
+            .extend(items.into_iter().map(|item| item.primitive));
+    }
+
+    fn build(&mut self, mut items: Vec<Item<P>>, leaf_size: usize, depth: usize) {
+        let bbox = bounds(items.iter().map(|item| &item.bbox)).unwrap();
+        let n = items.len();
+        if n == 1 || depth >= STACK_SIZE {
+            return self.leaf(items, bbox);
+        }
+
+        let centroid_bounds = items
+            .iter()
+            .map(|item| AABB::new(item.centroid, item.centroid))
+            .reduce(|a, b| surrounding_box(&a, &b))
+            .unwrap();
+        let extent = centroid_bounds.max - centroid_bounds.min;
+
+        let split = match BVH::best_split(&items, &centroid_bounds, bbox.surface_area()) {
+            Some((cost, axis, bin)) => {
+                let leaf_cost = n as f64 * INTERSECTION_COST;
+                if n <= leaf_size && leaf_cost <= cost {
+                    return self.leaf(items, bbox);
+                }
+                let (lo, size) = (centroid_bounds.min.get(axis), extent.get(axis));
+                let mut left = Vec::with_capacity(n);
+                let mut right = Vec::with_capacity(n);
+                for item in items.drain(..) {
+                    if bin_index(item.centroid.get(axis), lo, size) <= bin {
+                        left.push(item);
+                    } else {
+                        right.push(item);
+                    }
+                }
+                (axis, left, right)
+            }
+            // every centroid coincides, so no plane separates them
+            None if n <= leaf_size => return self.leaf(items, bbox),
+            None => {
+                let right = items.split_off(n / 2);
+                (0, items, right)
+            }
+        };
+
+        let (axis, left, right) = split;
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bbox,
+            node: Node::Branch {
+                second: 0,
+                axis: axis as u8,
+            },
+        });
+        self.build(left, leaf_size, depth + 1);
+        let second = self.nodes.len() as u32;
+        self.nodes[index].node = Node::Branch {
+            second,
+            axis: axis as u8,
+        };
+        self.build(right, leaf_size, depth + 1);
+    }
+
+    /// Cheapest split over all axes as `(cost, axis, last bin on the left)`
+    fn best_split(
+        items: &[Item<P>],
+        centroid_bounds: &AABB,
+        area: f64,
+    ) -> Option<(f64, usize, usize)> {
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            let (lo, size) = (centroid_bounds.min.get(axis), extent.get(axis));
+            if size <= 0.0 {
+                continue;
+            }
+            let mut counts = [0usize; SAH_BINS];
+            let mut boxes: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+            for item in items {
+                let b = bin_index(item.centroid.get(axis), lo, size);
+                counts[b] += 1;
+                boxes[b] = Some(match boxes[b] {
+                    Some(bbox) => surrounding_box(&bbox, &item.bbox),
+                    None => item.bbox,
+                });
+            }
+
+            // sweep from the right to know the cost of every right-hand side up front
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let (mut acc, mut count) = (None, 0);
+            for b in (1..SAH_BINS).rev() {
+                acc = merge(acc, boxes[b]);
+                count += counts[b];
+                right_area[b] = acc.map_or(0.0, |bbox| bbox.surface_area());
+                right_count[b] = count;
+            }
+
+            let (mut acc, mut count) = (None, 0);
+            for b in 0..SAH_BINS - 1 {
+                acc = merge(acc, boxes[b]);
+                count += counts[b];
+                if count == 0 || right_count[b + 1] == 0 {
+                    continue;
+                }
+                let left_area = acc.map_or(0.0, |bbox| bbox.surface_area());
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST
+                        * (left_area * count as f64
+                            + right_area[b + 1] * right_count[b + 1] as f64)
+                        / area;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, b));
                 }
             }
         }
+        best
+    }
 
-        fn axis_range(hit: &Vec<Box<dyn Hittable>>, time0: f64, time1: f64, axis: usize) -> f64 {
-            let (min, max) = hit.iter().fold((f64::MAX, f64::MIN), |(bmin, bmax), hit| {
-                if let Some(aabb) = hit.bounding_box(time0, time1) {
-                    (bmin.min(aabb.min.get(axis)), bmax.max(aabb.max.get(axis)))
-                } else {
-                    (bmin, bmax)
+    /// Shape of the tree and its expected cost per ray under the surface area heuristic
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats {
+            nodes: self.nodes.len(),
+            leaves: 0,
+            primitives: self.primitives.len(),
+            max_leaf_size: 0,
+            depth: 0,
+            cost: 0.0,
+        };
+        let root_area = self.nodes[0].bbox.surface_area();
+        let mut stack = vec![(0, 1)];
+        while let Some((index, depth)) = stack.pop() {
+            let LinearNode { bbox, node } = self.nodes[index];
+            stats.depth = stats.depth.max(depth);
+            let p = if root_area > 0.0 {
+                bbox.surface_area() / root_area
+            } else {
+                1.0
+            };
+            match node {
+                Node::Branch { second, .. } => {
+                    stats.cost += p * TRAVERSAL_COST;
+                    stack.push((index + 1, depth + 1));
+                    stack.push((second as usize, depth + 1));
+                }
+                Node::Leaf { count, .. } => {
+                    stats.leaves += 1;
+                    stats.max_leaf_size = stats.max_leaf_size.max(count as usize);
+                    stats.cost += p * INTERSECTION_COST * count as f64;
                 }
-            });
-            max - min
+            }
         }
+        stats
+    }
+}
 
-        // find the axis and the greatest range for this set of objects by Closure and Iterator
-        let mut axis_ranges: Vec<(usize, f64)> = (0..3)
-            .map(|a| (a, axis_range(&hit, time0, time1, a)))
-            .collect();
-        // reversed comparison function, to sort descending:
-        axis_ranges.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-        let axis = axis_ranges[0].0;
-
-        // sort objects along it by  widest extension of bounding box along that axis
-        hit.sort_unstable_by(box_compare(time0, time1, axis));
-
-        let length = hit.len();
-        match length {
-            0 => panic!("no object in the scene"),
-            1 => {
-                let leaf = hit.pop().unwrap();
-                if let Some(bbox) = leaf.bounding_box(time0, time1) {
-                    BVH {
-                        tree: BVHNode::Leaf(leaf),
-                        bbox,
+fn bin_index(x: f64, lo: f64, size: f64) -> usize {
+    if size <= 0.0 {
+        return 0;
+    }
+    (((x - lo) / size * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    pub max_leaf_size: usize,
+    pub depth: usize,
+    /// Expected cost of tracing a ray, in units of one primitive intersection
+    pub cost: f64,
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} nodes ({} leaves, {:.2} primitives per leaf, at most {}), depth {}, SAH cost {:.2}",
+            self.primitives,
+            self.nodes,
+            self.leaves,
+            self.primitives as f64 / self.leaves as f64,
+            self.max_leaf_size,
+            self.depth,
+            self.cost
+        )
+    }
+}
+
+impl<P> BVH<P> {
+    pub fn bbox(&self) -> AABB {
+        self.nodes[0].bbox
+    }
+
+    /// The primitives in the order the leaves hold them
+    pub fn primitives(&self) -> &[P] {
+        &self.primitives
+    }
+
+    /// Find the closest hit along `r`, intersecting primitives with `hit`
+    ///
+    /// `hit` is given the upper bound of the current search interval, which shrinks as closer
+    /// hits are found.
+    pub fn traverse<'a, F>(
+        &'a self,
+        r: &Ray,
+        t_min: f64,
+        mut t_max: f64,
+        mut hit: F,
+    ) -> Option<HitRecord<'a>>
+    where
+        F: FnMut(&'a P, f64) -> Option<HitRecord<'a>>,
+    {
+        let origin = r.origin();
+        let d = r.direction();
+        let inv_dir = Vec3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut closest = None;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut top = 0;
+        let mut index = 0;
+        loop {
+            let LinearNode { bbox, node } = &self.nodes[index];
+            if bbox.hit_inv(origin, inv_dir, t_min, t_max) {
+                match *node {
+                    Node::Branch { second, axis } => {
+                        // visit the child on the near side of the split first, so that hits there
+                        // can cull the far one
+                        let first = index as u32 + 1;
+                        let (near, far) = if dir_is_neg[axis as usize] {
+                            (second, first)
+                        } else {
+                            (first, second)
+                        };
+                        stack[top] = far;
+                        top += 1;
+                        index = near as usize;
+                        continue;
+                    }
+                    Node::Leaf { first, count } => {
+                        let range = first as usize..(first + count) as usize;
+                        for primitive in &self.primitives[range] {
+                            if let Some(rec) = hit(primitive, t_max) {
+                                t_max = rec.t;
+                                closest = Some(rec);
+                            }
+                        }
                     }
-                } else {
-                    panic!("no bounding box in bvh node")
                 }
             }
-            _ => {
-                let right = BVH::new(hit.drain(length / 2..).collect(), time0, time1);
-                // half the hit moved
-                let left = BVH::new(hit, time0, time1);
-                let bbox = surrounding_box(&left.bbox, &right.bbox);
-                BVH {
-                    tree: BVHNode::Branch {
-                        left: Box::new(left),
-                        right: Box::new(right),
-                    },
-                    bbox,
-                }
+            if top == 0 {
+                break;
             }
+            top -= 1;
+            index = stack[top] as usize;
         }
+        closest
     }
-}
 
-impl Hittable for BVH {
-    fn hit(&self, r: &Ray, t_min: f64, mut t_max: f64) -> Option<HitRecord> {
-        if self.bbox.hit(r, t_min, t_max) {
-            match &self.tree {
-                BVHNode::Branch { left, right } => {
-                    let left = left.hit(r, t_min, t_max);
-                    if let Some(l) = &left {
-                        t_max = l.t
-                    };
-                    let right = right.hit(r, t_min, t_max);
-                    if right.is_some() {
-                        right
-                    } else {
-                        left
+    /// Call `visit` on every primitive in a leaf whose box `r` passes through between `t_min`
+    /// and `t_max`, in no particular order, until it returns `false`
+    pub fn visit<F>(&self, r: &Ray, t_min: f64, t_max: f64, mut visit: F)
+    where
+        F: FnMut(&P) -> bool,
+    {
+        let origin = r.origin();
+        let d = r.direction();
+        let inv_dir = Vec3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut top = 0;
+        let mut index = 0;
+        loop {
+            let LinearNode { bbox, node } = &self.nodes[index];
+            if bbox.hit_inv(origin, inv_dir, t_min, t_max) {
+                match *node {
+                    Node::Branch { second, .. } => {
+                        stack[top] = second;
+                        top += 1;
+                        index += 1;
+                        continue;
+                    }
+                    Node::Leaf { first, count } => {
+                        let range = first as usize..(first + count) as usize;
+                        for primitive in &self.primitives[range] {
+                            if !visit(primitive) {
+                                return;
+                            }
+                        }
                     }
                 }
-                BVHNode::Leaf(leaf) => leaf.hit(r, t_min, t_max),
             }
-        } else {
-            None
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            index = stack[top] as usize;
         }
     }
+}
+
+impl Hittable for BVH {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        self.traverse(r, t_min, t_max, |hittable, t_max| {
+            hittable.hit(r, t_min, t_max, rng)
+        })
+    }
 
     fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
-        Some(self.bbox)
+        Some(self.bbox())
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        let mut transmittance = 1.0;
+        self.visit(r, t_min, t_max, |hittable| {
+            transmittance *= hittable.transmittance(r, t_min, t_max, rng);
+            transmittance > 0.0
+        });
+        transmittance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        geom::{
+            quad::{Plane, Quad},
+            sphere::Sphere,
+        },
+        render::{color::Color, mat::Lambertian, texture::ConstantTexture},
+        world::hittablelist::HittableList,
+    };
+
+    /// Spheres and quads scattered over a box, the same for the same seed
+    fn objects(seed: u64) -> Vec<Box<dyn Hittable>> {
+        let mut rng = Sampler::seed_from_u64(seed);
+        let material = Lambertian::new(ConstantTexture::new(Color::new(0.5, 0.5, 0.5)));
+        (0..300)
+            .map(|i| -> Box<dyn Hittable> {
+                let center = Vec3::random_range(&mut rng, -10.0..10.0);
+                if i % 3 == 0 {
+                    let plane = [Plane::XY, Plane::XZ, Plane::YZ][i % 9 / 3].clone();
+                    let size = rng.gen_range(0.1..2.0);
+                    let (a, b, k) = (center.x, center.y, center.z);
+                    Box::new(Quad::new(plane, a, a + size, b, b + size, k, material))
+                } else {
+                    Box::new(Sphere::new(center, rng.gen_range(0.05..1.0), material))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let list = HittableList { list: objects(1) };
+        let mut rng = Sampler::seed_from_u64(2);
+        let mut hits = 0;
+        for leaf_size in [1, 4, 16] {
+            let bvh = BVH::with_leaf_size(objects(1), 0.0, 1.0, leaf_size);
+            for _ in 0..5000 {
+                let origin = Vec3::random_range(&mut rng, -15.0..15.0);
+                let direction = Vec3::random_unit_vector(&mut rng);
+                let r = Ray::new(origin, direction, 0.0);
+                let t_max = rng.gen_range(1.0..40.0);
+                let expected = list.hit(&r, 0.001, t_max, &mut rng);
+                let actual = bvh.hit(&r, 0.001, t_max, &mut rng);
+                match (expected, actual) {
+                    (None, None) => {}
+                    (Some(expected), Some(actual)) => {
+                        hits += 1;
+                        assert_eq!(expected.t, actual.t);
+                        assert_eq!(expected.position, actual.position);
+                        assert_eq!(expected.normal, actual.normal);
+                    }
+                    (expected, actual) => panic!(
+                        "leaf size {}: brute force hit at {:?}, the bvh at {:?}",
+                        leaf_size,
+                        expected.map(|rec| rec.t),
+                        actual.map(|rec| rec.t)
+                    ),
+                }
+                assert_eq!(
+                    list.transmittance(&r, 0.001, t_max, &mut rng),
+                    bvh.transmittance(&r, 0.001, t_max, &mut rng)
+                );
+            }
+        }
+        // misses are compared too, but without enough hits the comparison says little
+        assert!(hits > 1000, "only {} hits", hits);
     }
 }
@@ -1,7 +1,11 @@
+use std::sync::Arc;
+
 use crate::geom::ray::Ray;
 use crate::geom::vec3::{Point3, Vec3};
 use crate::hit::aabb::AABB;
+use crate::render::color::Color;
 use crate::render::mat::Material;
+use crate::render::sampler::Sampler;
 
 pub struct HitRecord<'a> {
     pub position: Point3,
@@ -11,17 +15,100 @@ pub struct HitRecord<'a> {
     pub v: f64, // texture coordinates
     pub front_face: bool,
     pub material: &'a dyn Material,
+    /// Vertex colour interpolated over the face, for meshes that have them
+    pub color: Option<Color>,
 }
 
-pub trait Hittable: Sync {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f64 {
         0.0
     }
-    fn random(&self, o: Vec3) -> Vec3 {
+    fn random(&self, _o: Vec3, _rng: &mut Sampler) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+    /// Fraction of the light travelling along `r` between `t_min` and `t_max` that gets through
+    ///
+    /// Surfaces block it, participating media attenuate it.
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        if self.hit(r, t_min, t_max, rng).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+    /// A point uniformly distributed over the surface, with its outward normal and the density
+    /// of that choice over area
+    ///
+    /// Light paths start on lights through this; surfaces that cannot be sampled give `None`.
+    fn sample_surface(&self, _rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        None
+    }
+    /// Density over area with which `sample_surface` picks the first point `r` hits
+    fn surface_pdf(&self, _r: &Ray) -> f64 {
+        0.0
+    }
+}
+
+impl<H: Hittable + ?Sized> Hittable for Box<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        (**self).hit(r, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        (**self).bounding_box(t0, t1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        (**self).pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        (**self).random(o, rng)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        (**self).transmittance(r, t_min, t_max, rng)
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        (**self).sample_surface(rng)
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        (**self).surface_pdf(r)
+    }
+}
+
+impl<H: Hittable + ?Sized> Hittable for Arc<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        (**self).hit(r, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        (**self).bounding_box(t0, t1)
+    }
+
+    fn pdf_value(&self, o: Point3, v: Vec3) -> f64 {
+        (**self).pdf_value(o, v)
+    }
+
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        (**self).random(o, rng)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        (**self).transmittance(r, t_min, t_max, rng)
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        (**self).sample_surface(rng)
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        (**self).surface_pdf(r)
+    }
 }
 
 impl HitRecord<'_> {
@@ -47,8 +134,8 @@ impl<H: Hittable> FlipNormal<H> {
 }
 
 impl<H: Hittable> Hittable for FlipNormal<H> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.hittable.hit(&r, t_min, t_max).map(|mut rec| {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
+        self.hittable.hit(r, t_min, t_max, rng).map(|mut rec| {
             rec.front_face = !rec.front_face;
             rec
         })
@@ -62,7 +149,22 @@ impl<H: Hittable> Hittable for FlipNormal<H> {
         self.hittable.pdf_value(o, v)
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
-        self.hittable.random(o)
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        self.hittable.random(o, rng)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        self.hittable.transmittance(r, t_min, t_max, rng)
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        self.hittable.sample_surface(rng).map(|(mut rec, pdf)| {
+            rec.normal = -rec.normal;
+            (rec, pdf)
+        })
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        self.hittable.surface_pdf(r)
     }
 }
@@ -1,3 +1,5 @@
+#![allow(clippy::upper_case_acronyms)]
+
 mod cfg;
 mod geom;
 mod hit;
@@ -6,61 +8,170 @@ mod transform;
 mod utils;
 mod world;
 
-use cfg::*;
-use image::{ImageBuffer, RgbImage};
-use indicatif::ProgressBar;
-use rand::Rng;
-use rayon::prelude::*;
-use render::color::{ray_color, Color};
-use world::scene::scene_select;
+use std::time::Instant;
+
+use cfg::Config;
+use render::{
+    film::{CheckpointHeader, Film},
+    integrator::integrator_select,
+    renderer::{Adaptive, Renderer, Scene},
+    tonemap::ToneMap,
+};
+use world::{gltf_loader::load_gltf, loader::load_scene, scene::scene_select};
+
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
+
+/// Write the film to every output path
+fn save_outputs(cfg: &Config, film: &Film) -> Result<(), String> {
+    let tonemap = ToneMap {
+        exposure: cfg.exposure,
+        operator: cfg.tonemap,
+        transfer: cfg.transfer,
+    };
+    for output in &cfg.output {
+        film.save(output, &tonemap)?;
+    }
+    if let Some(heatmap) = &cfg.heatmap {
+        film.save_heatmap(heatmap)?;
+    }
+    Ok(())
+}
 
 fn main() {
-    // World
-    let (world, lights, bgcolor, camera) = scene_select(SCENE_SELECTOR);
+    let mut cfg = Config::from_args();
+    if let Some(threads) = cfg.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("failed to build the thread pool");
+    }
 
-    // Image
-    let mut img: RgbImage = ImageBuffer::new(IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32);
-    let bar = ProgressBar::new(IMAGE_HEIGHT);
+    let image_width = cfg.image_width();
+    let image_height = cfg.image_height();
+    let samples_per_pixel = cfg.samples_per_pixel;
+    let settings = cfg.settings_hash();
+    let mut film = match &cfg.resume {
+        Some(path) => {
+            let (film, header) = exit_on_error(Film::load_checkpoint(path));
+            if (film.width(), film.height()) != (image_width, image_height) {
+                eprintln!(
+                    "{}: checkpoint is {}x{}, but the image is {}x{}",
+                    path.display(),
+                    film.width(),
+                    film.height(),
+                    image_width,
+                    image_height
+                );
+                std::process::exit(1);
+            }
+            if header.settings != settings {
+                eprintln!(
+                    "{}: checkpoint was rendered with another scene or other settings",
+                    path.display()
+                );
+                std::process::exit(1);
+            }
+            println!(
+                "Resuming {} from {} spp",
+                path.display(),
+                film.max_samples()
+            );
+            cfg.seed = Some(header.seed);
+            cfg.photon_radius = header.photon_radius.or(cfg.photon_radius);
+            film
+        }
+        None => Film::new(image_width, image_height),
+    };
+    println!(
+        "Rendering {} at {}x{}, {} spp, seed {}",
+        match &cfg.scene_file {
+            Some(path) => path.display().to_string(),
+            None => format!("{:?}", cfg.scene),
+        },
+        image_width,
+        image_height,
+        samples_per_pixel,
+        cfg.seed()
+    );
+
+    // World
+    let (world, lights, bgcolor, camera) = match &cfg.scene_file {
+        Some(path) => match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("gltf") || ext.eq_ignore_ascii_case("glb") => {
+                exit_on_error(load_gltf(path, &cfg))
+            }
+            _ => exit_on_error(load_scene(path, &cfg)),
+        },
+        None => exit_on_error(scene_select(&cfg)),
+    };
 
     // Timer
-    let start = std::time::Instant::now();
+    let start = Instant::now();
 
     // Render
-    for j in 0..IMAGE_HEIGHT {
-        for i in 0..IMAGE_WIDTH {
-            let pixel_color: Color = (0..SAMPLES_PER_PIXEL)
-                .into_par_iter()
-                .map(|_sample| {
-                    let mut rng = rand::thread_rng();
-                    let random_u = rng.gen::<f64>();
-                    let random_v = rng.gen::<f64>();
-
-                    let u = ((i as f64) + random_u) / ((IMAGE_WIDTH - 1) as f64);
-                    let v = ((j as f64) + random_v) / ((IMAGE_HEIGHT - 1) as f64);
-
-                    let r = camera.get_ray(u, v);
-                    ray_color(&r, bgcolor, &world, &lights, MAX_DEPTH)
-                })
-                .sum();
-            let pixel = img.get_pixel_mut(i as u32, (IMAGE_HEIGHT - j - 1) as u32);
-            let pixel_color = pixel_color.output(SAMPLES_PER_PIXEL);
-            *pixel = image::Rgb([
-                pixel_color.x as u8,
-                pixel_color.y as u8,
-                pixel_color.z as u8,
-            ]);
-        }
-        bar.inc(1);
-        // Timer
-        let duration = start.elapsed();
-        // Estimated time remaining
-        let remaining = (duration.as_secs_f64() / (j + 1) as f64) * (IMAGE_HEIGHT - j - 1) as f64;
+    let integrator = integrator_select(&cfg);
+    let renderer = Renderer {
+        scene: Scene {
+            world: &*world,
+            lights: &*lights,
+            background: bgcolor,
+            camera: &camera,
+            width: image_width,
+            height: image_height,
+        },
+        samples_per_pixel,
+        integrator: &*integrator,
+        tile_size: cfg.tile_size,
+        seed: cfg.seed(),
+        adaptive: cfg.adaptive.then(|| Adaptive {
+            min_samples: cfg.min_spp,
+            max_samples: cfg.max_spp.unwrap_or(samples_per_pixel),
+            threshold: cfg.noise_threshold,
+        }),
+    };
+    if let Some(rays) = cfg.benchmark {
+        let (hits, elapsed) = renderer.benchmark(rays);
         println!(
-            "Time elapsed is: {:?}, estimated time remaining is: {:?}",
-            duration, remaining
+            "Traced {} rays in {:?} ({:.2} Mrays/s), {} hit",
+            rays,
+            elapsed,
+            rays as f64 / elapsed.as_secs_f64() / 1e6,
+            hits
         );
+        return;
     }
 
-    bar.finish();
-    img.save("output/test.png").unwrap();
+    let header = || CheckpointHeader {
+        seed: cfg.seed(),
+        settings,
+        photon_radius: integrator.photon_radius(),
+    };
+    let mut last_checkpoint = Instant::now();
+    renderer.render(&mut film, cfg.pass_spp, |film| {
+        let Some(checkpoint) = &cfg.checkpoint else {
+            return;
+        };
+        if last_checkpoint.elapsed().as_secs_f64() < cfg.checkpoint_interval {
+            return;
+        }
+        // a failed checkpoint should not bring down the render
+        if let Err(err) = film
+            .save_checkpoint(checkpoint, &header())
+            .and_then(|_| save_outputs(&cfg, film))
+        {
+            eprintln!("{}", err);
+        }
+        last_checkpoint = Instant::now();
+    });
+    println!("Rendered in {:?}", start.elapsed());
+
+    if let Some(checkpoint) = &cfg.checkpoint {
+        exit_on_error(film.save_checkpoint(checkpoint, &header()));
+    }
+    exit_on_error(save_outputs(&cfg, &film));
 }
@@ -0,0 +1,473 @@
+use crate::{
+    geom::{
+        ray::Ray,
+        vec3::{Point3, Vec3},
+    },
+    hit::hittable::HitRecord,
+    utils::PI,
+};
+
+use super::{
+    color::{Color, SHADOW_EPSILON},
+    film::Splat,
+    integrator::Integrator,
+    mat::ScatterRecord,
+    pdf::PDF,
+    renderer::Scene,
+    sampler::Sampler,
+};
+
+enum Kind<'a> {
+    /// A point on the lens
+    Camera,
+    /// A point on a light, emitting around `normal`
+    Light { normal: Vec3 },
+    /// A hit reached along `r_in`, with no attenuation where the material does not scatter
+    Surface {
+        rec: HitRecord<'a>,
+        r_in: Ray,
+        attenuation: Option<Color>,
+    },
+}
+
+/// A vertex of a subpath from the camera or from a light
+struct Vertex<'a> {
+    kind: Kind<'a>,
+    position: Point3,
+    /// Light or importance carried to the vertex, over the density of the subpath leading to it
+    beta: Color,
+    /// Scatters specularly, so nothing can be joined to it
+    delta: bool,
+    /// Density over area of the vertex, sampled from its predecessor on the subpath
+    pdf_fwd: f64,
+    /// Density over area of the vertex, had its successor sampled it from the other end
+    pdf_rev: f64,
+}
+
+/// `rec` as a ray arriving along `r_in` would find it, its normal turned against the ray
+fn facing<'a>(rec: &HitRecord<'a>, r_in: &Ray) -> HitRecord<'a> {
+    let flip = rec.normal.dot(r_in.direction()) > 0.0;
+    HitRecord {
+        position: rec.position,
+        normal: if flip { -rec.normal } else { rec.normal },
+        t: rec.t,
+        u: rec.u,
+        v: rec.v,
+        front_face: rec.front_face != flip,
+        material: rec.material,
+        color: rec.color,
+    }
+}
+
+impl<'a> Vertex<'a> {
+    fn new(kind: Kind<'a>, position: Point3, beta: Color, pdf_fwd: f64) -> Vertex<'a> {
+        Vertex {
+            kind,
+            position,
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+        }
+    }
+
+    /// Absolute cosine between the unit vector `direction` and the surface at the vertex, 1 in
+    /// a medium, which has no surface to foreshorten it
+    fn cos(&self, scene: &Scene, direction: Vec3) -> f64 {
+        match &self.kind {
+            Kind::Camera => scene.camera.forward().dot(direction).abs(),
+            Kind::Light { normal } => normal.dot(direction).abs(),
+            Kind::Surface { rec, .. } if rec.material.is_volumetric() => 1.0,
+            Kind::Surface { rec, .. } => rec.normal.dot(direction).abs(),
+        }
+    }
+
+    fn connectible(&self) -> bool {
+        match &self.kind {
+            Kind::Surface { attenuation, .. } => attenuation.is_some() && !self.delta,
+            _ => true,
+        }
+    }
+
+    /// Fraction of what reached the vertex that leaves it in the unit vector `direction`, with
+    /// the cosine at the vertex
+    ///
+    /// Light subpaths evaluate materials with the directions swapped, which relies on them
+    /// being reciprocal.
+    fn scattering(&self, direction: Vec3) -> Color {
+        match &self.kind {
+            Kind::Light { normal } => normal.dot(direction).max(0.0) * Color::ones(),
+            Kind::Surface {
+                rec,
+                r_in,
+                attenuation: Some(attenuation),
+            } => {
+                let ray_out = Ray::new(self.position, direction, r_in.time());
+                *attenuation * rec.material.scattering(r_in, rec, &ray_out)
+            }
+            _ => Color::zero(),
+        }
+    }
+
+    /// Turn a density over solid angle of directions leaving the vertex into one over area at
+    /// `next`
+    fn to_area(&self, scene: &Scene, pdf: f64, next: &Vertex) -> f64 {
+        let to_next = next.position - self.position;
+        let distance_squared = to_next.squared_length();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        pdf * next.cos(scene, to_next / distance_squared.sqrt()) / distance_squared
+    }
+
+    /// Density over area with which a subpath that reached the vertex from `prev` goes on to
+    /// `next`
+    fn pdf(&self, scene: &Scene, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let direction = (next.position - self.position).unit();
+        let pdf = match &self.kind {
+            Kind::Camera => scene.direction_pdf(direction),
+            Kind::Light { normal } => normal.dot(direction).max(0.0) / PI,
+            Kind::Surface { rec, r_in, .. } => {
+                let prev = prev.expect("a surface vertex has a predecessor");
+                let r_in = Ray::new(
+                    prev.position,
+                    (self.position - prev.position).unit(),
+                    r_in.time(),
+                );
+                let ray_out = Ray::new(self.position, direction, r_in.time());
+                rec.material
+                    .scatter_pdf(&r_in, &facing(rec, &r_in), &ray_out)
+            }
+        };
+        self.to_area(scene, pdf, next)
+    }
+
+    /// Density over area with which a light subpath starting at the vertex heads to `next`
+    fn emission_pdf(&self, scene: &Scene, next: &Vertex) -> f64 {
+        let direction = (next.position - self.position).unit();
+        self.to_area(scene, self.cos(scene, direction) / PI, next)
+    }
+
+    /// Density over area with which light subpaths start at the vertex, zero unless it is on
+    /// one of the lights
+    fn light_origin_pdf(&self, scene: &Scene, rng: &mut Sampler) -> f64 {
+        let Kind::Surface { rec, r_in, .. } = &self.kind else {
+            return 0.0;
+        };
+        match scene.lights.hit(r_in, 0.00001, f64::INFINITY, rng) {
+            Some(light) if (light.t - rec.t).abs() <= SHADOW_EPSILON * rec.t => {
+                scene.lights.surface_pdf(r_in)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Extend `path` along `ray`, which left its last vertex with density `pdf` over solid angle,
+/// scattering until the path holds `max_vertices` vertices or ends
+///
+/// Returns the weight of a ray that leaves the scene.
+fn random_walk<'a>(
+    scene: &Scene<'a>,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf: f64,
+    path: &mut Vec<Vertex<'a>>,
+    max_vertices: usize,
+    rng: &mut Sampler,
+) -> Color {
+    while path.len() < max_vertices {
+        let Some(rec) = scene.world.hit(&ray, 0.00001, f64::INFINITY, rng) else {
+            return beta;
+        };
+        let position = rec.position;
+        let (attenuation, delta, next, pdf_rev) =
+            match rec.material.scatter_monte_carlo(&ray, &rec, rng) {
+                None => (None, false, None, 0.0),
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => (
+                    Some(attenuation),
+                    true,
+                    Some((specular_ray, attenuation, 0.0)),
+                    0.0,
+                ),
+                Some(ScatterRecord::Scatter {
+                    pdf: scatter_pdf,
+                    attenuation,
+                }) => {
+                    let scattered =
+                        Ray::new(position, scatter_pdf.generate(rng).unit(), ray.time());
+                    let pdf_value = scatter_pdf.value(scattered.direction());
+                    let next = (pdf_value > 0.0).then(|| {
+                        let weight = attenuation * rec.material.scattering(&ray, &rec, &scattered)
+                            / pdf_value;
+                        (scattered, weight, pdf_value)
+                    });
+                    let reversed = Ray::new(position, -scattered.direction(), ray.time());
+                    let back = Ray::new(position, -ray.direction().unit(), ray.time());
+                    let pdf_rev =
+                        rec.material
+                            .scatter_pdf(&reversed, &facing(&rec, &reversed), &back);
+                    (Some(attenuation), false, next, pdf_rev)
+                }
+            };
+
+        let prev = path.len() - 1;
+        let mut vertex = Vertex::new(
+            Kind::Surface {
+                rec,
+                r_in: ray,
+                attenuation,
+            },
+            position,
+            beta,
+            0.0,
+        );
+        vertex.delta = delta;
+        vertex.pdf_fwd = path[prev].to_area(scene, pdf, &vertex);
+        path[prev].pdf_rev = vertex.to_area(scene, pdf_rev, &path[prev]);
+        path.push(vertex);
+
+        let Some((scattered, weight, pdf_value)) = next else {
+            break;
+        };
+        beta = beta * weight;
+        pdf = pdf_value;
+        ray = scattered;
+    }
+    Color::zero()
+}
+
+/// Fraction of the light leaving `a` that reaches `b`
+fn visibility(scene: &Scene, a: Point3, b: Point3, time: f64, rng: &mut Sampler) -> f64 {
+    let distance = (b - a).length();
+    let ray = Ray::new(a, (b - a) / distance, time);
+    scene
+        .world
+        .transmittance(&ray, 0.00001, distance * (1.0 - SHADOW_EPSILON), rng)
+}
+
+/// Power heuristic weight of joining the subpaths `camera` and `light` at their ends, against
+/// every other split of the same path between a camera and a light subpath
+fn mis_weight(scene: &Scene, camera: &[Vertex], light: &[Vertex], rng: &mut Sampler) -> f64 {
+    let (s, t) = (light.len(), camera.len());
+    if s + t == 2 {
+        return 1.0;
+    }
+    let pt = &camera[t - 1];
+    let pt_minus = t.checked_sub(2).map(|i| &camera[i]);
+    let qs = light.last();
+    let qs_minus = s.checked_sub(2).map(|i| &light[i]);
+
+    // (forward, reverse, delta) of every vertex, updated around the connection
+    let pdfs = |path: &[Vertex]| -> Vec<(f64, f64, bool)> {
+        path.iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect()
+    };
+    let (mut camera_pdfs, mut light_pdfs) = (pdfs(camera), pdfs(light));
+    camera_pdfs[t - 1].1 = match qs {
+        Some(qs) => qs.pdf(scene, qs_minus, pt),
+        None => pt.light_origin_pdf(scene, rng),
+    };
+    if camera_pdfs[t - 1].1 == 0.0 && s == 0 {
+        // an emitter that is not one of the lights is only ever found from the camera
+        return 1.0;
+    }
+    camera_pdfs[t - 1].2 = false;
+    if let Some(pt_minus) = pt_minus {
+        camera_pdfs[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(scene, Some(qs), pt_minus),
+            None => pt.emission_pdf(scene, pt_minus),
+        };
+    }
+    if let Some(qs) = qs {
+        light_pdfs[s - 1].1 = pt.pdf(scene, pt_minus, qs);
+        light_pdfs[s - 1].2 = false;
+        if let Some(qs_minus) = qs_minus {
+            light_pdfs[s - 2].1 = qs.pdf(scene, Some(pt), qs_minus);
+        }
+    }
+
+    // relative density of the strategy one vertex further along, delta densities counting as 1
+    let ratio = |(fwd, rev, _): (f64, f64, bool)| {
+        let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+        (remap(rev) / remap(fwd)).powi(2)
+    };
+    let mut sum = 0.0;
+    let mut r = 1.0;
+    for i in (1..t).rev() {
+        r *= ratio(camera_pdfs[i]);
+        if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+            sum += r;
+        }
+    }
+    let mut r = 1.0;
+    for i in (0..s).rev() {
+        r *= ratio(light_pdfs[i]);
+        let delta_before = i > 0 && light_pdfs[i - 1].2;
+        if !light_pdfs[i].2 && !delta_before {
+            sum += r;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+/// Bidirectional path tracing: every vertex of a path from the camera is joined to every vertex
+/// of a path from a point on one of the lights, and the light path is also joined to the lens
+/// and splatted onto the pixel that sees it
+///
+/// Each way of building a path is weighted against the others by multiple importance sampling,
+/// so caustics come from light paths and everything else mostly from camera paths. Light paths
+/// only start on lights that can sample their surface.
+pub struct BidirectionalPathTracer {
+    pub max_depth: u64,
+}
+
+impl BidirectionalPathTracer {
+    fn light_path<'a>(&self, scene: &Scene<'a>, time: f64, rng: &mut Sampler) -> Vec<Vertex<'a>> {
+        let mut path = Vec::new();
+        let Some((rec, pdf_position)) = scene.lights.sample_surface(rng) else {
+            return path;
+        };
+        let emitted = rec.material.emitted(&rec);
+        if pdf_position <= 0.0 || emitted.luminance() <= 0.0 {
+            return path;
+        }
+        let normal = rec.normal;
+        let direction = PDF::cosine_pdf(normal).generate(rng).unit();
+        let pdf = normal.dot(direction) / PI;
+        path.push(Vertex::new(
+            Kind::Light { normal },
+            rec.position,
+            emitted / pdf_position,
+            pdf_position,
+        ));
+        if pdf > 0.0 {
+            let beta = emitted * normal.dot(direction) / (pdf_position * pdf);
+            let ray = Ray::new(rec.position, direction, time);
+            random_walk(
+                scene,
+                ray,
+                beta,
+                pdf,
+                &mut path,
+                self.max_depth as usize,
+                rng,
+            );
+        }
+        path
+    }
+
+    /// Light carried by joining the ends of `camera`, at least two vertices long, and `light`
+    fn connect(
+        scene: &Scene,
+        camera: &[Vertex],
+        light: &[Vertex],
+        time: f64,
+        rng: &mut Sampler,
+    ) -> Color {
+        let pt = camera.last().expect("camera subpaths start at the lens");
+        let contribution = match light.last() {
+            None => match &pt.kind {
+                Kind::Surface { rec, .. } => pt.beta * rec.material.emitted(rec),
+                _ => Color::zero(),
+            },
+            Some(qs) => {
+                if !pt.connectible() || !qs.connectible() {
+                    return Color::zero();
+                }
+                let to_light = qs.position - pt.position;
+                let distance_squared = to_light.squared_length();
+                let direction = to_light / distance_squared.sqrt();
+                let unoccluded =
+                    pt.beta * pt.scattering(direction) * qs.scattering(-direction) * qs.beta
+                        / distance_squared;
+                if unoccluded.luminance() <= 0.0 {
+                    return Color::zero();
+                }
+                unoccluded * visibility(scene, pt.position, qs.position, time, rng)
+            }
+        };
+        if contribution.luminance() <= 0.0 {
+            return Color::zero();
+        }
+        contribution * mis_weight(scene, camera, light, rng)
+    }
+
+    /// Light tracing: what the end of `light` sends straight to a point on the lens, for the
+    /// pixel that sees it
+    fn splat(scene: &Scene, light: &[Vertex], time: f64, rng: &mut Sampler) -> Option<Splat> {
+        let qs = light.last()?;
+        if !qs.connectible() {
+            return None;
+        }
+        let lens = Vertex::new(
+            Kind::Camera,
+            scene.camera.sample_lens(rng),
+            Color::ones(),
+            1.0,
+        );
+        let to_lens = lens.position - qs.position;
+        let distance_squared = to_lens.squared_length();
+        let direction = to_lens / distance_squared.sqrt();
+        let (x, y) = scene.raster(lens.position, -direction)?;
+        // the camera's importance and the density of the lens point cancel down to this
+        let importance = scene.direction_pdf(-direction);
+        let unoccluded = qs.beta * qs.scattering(direction) * importance / distance_squared;
+        if unoccluded.luminance() <= 0.0 {
+            return None;
+        }
+        let radiance = unoccluded
+            * visibility(scene, qs.position, lens.position, time, rng)
+            * mis_weight(scene, std::slice::from_ref(&lens), light, rng);
+        Some(Splat { x, y, radiance })
+    }
+}
+
+impl Integrator for BidirectionalPathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        let max_vertices = self.max_depth as usize + 1;
+        let mut camera_path = vec![Vertex::new(Kind::Camera, ray.origin(), Color::ones(), 1.0)];
+        let escaped = random_walk(
+            scene,
+            *ray,
+            Color::ones(),
+            scene.direction_pdf(ray.direction()),
+            &mut camera_path,
+            max_vertices,
+            rng,
+        );
+        let light_path = self.light_path(scene, ray.time(), rng);
+
+        let mut radiance = escaped * scene.background;
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || (s == 1 && t == 1) || s + t > max_vertices {
+                    continue;
+                }
+                let (camera, light) = (&camera_path[..t], &light_path[..s]);
+                if t == 1 {
+                    splats.extend(BidirectionalPathTracer::splat(
+                        scene,
+                        light,
+                        ray.time(),
+                        rng,
+                    ));
+                } else {
+                    radiance +=
+                        BidirectionalPathTracer::connect(scene, camera, light, ray.time(), rng);
+                }
+            }
+        }
+        radiance
+    }
+}
@@ -1,60 +1,67 @@
-﻿use crate::{geom::ray::Ray, geom::vec3::Vec3, hit::hittable::Hittable};
+use rand::Rng;
+
+use crate::{
+    geom::ray::Ray,
+    geom::vec3::Vec3,
+    hit::hittable::{HitRecord, Hittable},
+};
 
 use super::mat::ScatterRecord;
 use super::pdf::PDF;
+use super::sampler::Sampler;
 
 pub type Color = Vec3;
 
 impl Color {
-    pub fn output(self, samples_per_pixel: u64) -> Self {
-        let r = 256.0
-            * (self.x / (samples_per_pixel as f64))
-                .sqrt()
-                .clamp(0.0, 0.999);
-        let g = 256.0
-            * (self.y / (samples_per_pixel as f64))
-                .sqrt()
-                .clamp(0.0, 0.999);
-        let b = 256.0
-            * (self.z / (samples_per_pixel as f64))
-                .sqrt()
-                .clamp(0.0, 0.999);
-
-        Color::new(r, g, b)
+    /// Relative luminance with Rec. 709 primaries
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
     }
 }
 
+/// Radiance along `ray`, scattering in directions drawn from an even mixture of the lights and
+/// the material, and adding emission wherever a path happens to hit it
 pub fn ray_color(
     ray: &Ray,
     background: Color,
-    world: &Box<dyn Hittable>,
-    lights: &Box<dyn Hittable>,
+    world: &dyn Hittable,
+    lights: &dyn Hittable,
     depth: u64,
+    rng: &mut Sampler,
 ) -> Color {
     if depth == 0 {
         return Color::new(0.0, 0.0, 0.0);
     }
-    if let Some(rec) = world.hit(ray, 0.00001, f64::INFINITY) {
+    if let Some(rec) = world.hit(ray, 0.00001, f64::INFINITY, rng) {
         let emitted: Color = rec.material.emitted(&rec);
-        if let Some(srec) = rec.material.scatter_monte_carlo(ray, &rec) {
+        if let Some(srec) = rec.material.scatter_monte_carlo(ray, &rec, rng) {
             match srec {
                 ScatterRecord::Specular {
                     specular_ray,
                     attenuation,
                 } => {
-                    return attenuation
-                        * ray_color(&specular_ray, background, world, lights, depth - 1)
+                    attenuation
+                        * ray_color(&specular_ray, background, world, lights, depth - 1, rng)
                 }
                 ScatterRecord::Scatter { pdf, attenuation } => {
                     let hittable_pdf = PDF::hittable_pdf(rec.position, lights);
                     let mixture_pdf = PDF::mixture_pdf(&hittable_pdf, &pdf);
-                    let scattered = Ray::new(rec.position, mixture_pdf.generate(), ray.time());
+                    // an empty light list has no bounding box and nothing to sample
+                    let mixture_pdf = if lights.bounding_box(0.0, 1.0).is_some() {
+                        &mixture_pdf
+                    } else {
+                        &pdf
+                    };
+                    let scattered = Ray::new(rec.position, mixture_pdf.generate(rng), ray.time());
                     let pdf_value = mixture_pdf.value(scattered.direction());
-                    return emitted
+                    if pdf_value <= 0.0 {
+                        return emitted;
+                    }
+                    emitted
                         + attenuation
-                            * rec.material.scatter_pdf(ray, &rec, &scattered)
-                            * ray_color(&scattered, background, world, lights, depth - 1)
-                            / pdf_value;
+                            * rec.material.scattering(ray, &rec, &scattered)
+                            * ray_color(&scattered, background, world, lights, depth - 1, rng)
+                            / pdf_value
                 }
             }
         } else {
@@ -64,3 +71,140 @@ pub fn ray_color(
         background
     }
 }
+
+/// Shadow rays stop this fraction of the distance short of the light they were aimed at
+pub const SHADOW_EPSILON: f64 = 1e-6;
+
+/// Power heuristic weight, with exponent 2, of a sample drawn with density `pdf` by one of two
+/// strategies, the other having density `other` for it
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Density with which next-event estimation picks the direction of `ray`, given that it reached
+/// an emitter at `t`
+///
+/// Light sampling only ever finds the first of the lights along a direction, so an emitter
+/// behind another light, or one that is not a light at all, can only be reached by scattering.
+pub fn light_pdf(lights: &dyn Hittable, ray: &Ray, t: f64, rng: &mut Sampler) -> f64 {
+    match lights.hit(ray, 0.00001, f64::INFINITY, rng) {
+        Some(light) if (light.t - t).abs() <= SHADOW_EPSILON * t => {
+            lights.pdf_value(ray.origin(), ray.direction())
+        }
+        _ => 0.0,
+    }
+}
+
+/// Next-event estimation: light reaching `rec` straight from a point sampled on `lights`,
+/// weighted against the material drawing the same direction from `pdf`
+pub fn sample_light(
+    ray: &Ray,
+    rec: &HitRecord,
+    pdf: &PDF,
+    world: &dyn Hittable,
+    lights: &dyn Hittable,
+    rng: &mut Sampler,
+) -> Color {
+    // an empty light list has no bounding box and nothing to sample
+    if lights.bounding_box(0.0, 1.0).is_none() {
+        return Color::zero();
+    }
+    let shadow_ray = Ray::new(
+        rec.position,
+        lights.random(rec.position, rng).unit(),
+        ray.time(),
+    );
+    let light_pdf = lights.pdf_value(rec.position, shadow_ray.direction());
+    let Some(light) = lights.hit(&shadow_ray, 0.00001, f64::INFINITY, rng) else {
+        return Color::zero();
+    };
+    let emitted = light.material.emitted(&light);
+    if light_pdf <= 0.0 || emitted.luminance() <= 0.0 {
+        return Color::zero();
+    }
+    let t_max = light.t * (1.0 - SHADOW_EPSILON);
+    let transmittance = world.transmittance(&shadow_ray, 0.00001, t_max, rng);
+    if transmittance <= 0.0 {
+        return Color::zero();
+    }
+    let weight = power_heuristic(light_pdf, pdf.value(shadow_ray.direction()));
+    rec.material.scattering(ray, rec, &shadow_ray) * emitted * transmittance * weight / light_pdf
+}
+
+/// Radiance along `ray`, sampling the lights at every scattering vertex and combining those
+/// samples with the material's own by multiple importance sampling
+///
+/// Paths are followed for at most `max_depth` hits. After `rr_min_bounces` bounces Russian
+/// roulette ends them with a probability that grows as their throughput falls, and boosts the
+/// survivors to make up for it.
+pub fn ray_color_mis(
+    ray: &Ray,
+    background: Color,
+    world: &dyn Hittable,
+    lights: &dyn Hittable,
+    max_depth: u64,
+    rr_min_bounces: u64,
+    rng: &mut Sampler,
+) -> Color {
+    let mut radiance = Color::zero();
+    let mut throughput = Color::ones();
+    let mut ray = *ray;
+    // density the material drew `ray` with, none for camera and specular rays whose emission
+    // light sampling cannot find
+    let mut scatter_pdf = None;
+
+    for bounce in 0..max_depth {
+        let Some(rec) = world.hit(&ray, 0.00001, f64::INFINITY, rng) else {
+            radiance += throughput * background;
+            break;
+        };
+        let mut emitted = rec.material.emitted(&rec);
+        if let Some(scatter_pdf) = scatter_pdf {
+            if emitted.luminance() > 0.0 {
+                emitted *= power_heuristic(scatter_pdf, light_pdf(lights, &ray, rec.t, rng));
+            }
+        }
+        radiance += throughput * emitted;
+
+        match rec.material.scatter_monte_carlo(&ray, &rec, rng) {
+            None => break,
+            Some(ScatterRecord::Specular {
+                specular_ray,
+                attenuation,
+            }) => {
+                throughput = throughput * attenuation;
+                ray = specular_ray;
+                scatter_pdf = None;
+            }
+            Some(ScatterRecord::Scatter { pdf, attenuation }) => {
+                let direct = sample_light(&ray, &rec, &pdf, world, lights, rng);
+                radiance += throughput * attenuation * direct;
+
+                let scattered = Ray::new(rec.position, pdf.generate(rng), ray.time());
+                let pdf_value = pdf.value(scattered.direction());
+                if pdf_value <= 0.0 {
+                    break;
+                }
+                throughput =
+                    throughput * attenuation * rec.material.scattering(&ray, &rec, &scattered)
+                        / pdf_value;
+                ray = scattered;
+                scatter_pdf = Some(pdf_value);
+            }
+        }
+
+        if bounce + 1 >= rr_min_bounces {
+            let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+            if rng.gen::<f64>() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+    }
+    radiance
+}
@@ -0,0 +1,452 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use image::{ImageBuffer, Rgb32FImage, RgbImage};
+
+use super::{color::Color, tonemap::ToneMap};
+
+/// Running statistics of the samples of one pixel
+///
+/// Radiance is summed per channel; mean and variance of the luminance are tracked with
+/// Welford's algorithm to estimate how noisy the pixel still is.
+#[derive(Copy, Clone, Debug)]
+pub struct PixelStats {
+    pub sum: Color,
+    pub samples: u64,
+    luminance_mean: f64,
+    luminance_m2: f64,
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        PixelStats {
+            sum: Color::zero(),
+            samples: 0,
+            luminance_mean: 0.0,
+            luminance_m2: 0.0,
+        }
+    }
+}
+
+impl PixelStats {
+    pub fn push(&mut self, color: Color) {
+        self.sum += color;
+        self.samples += 1;
+        let luminance = color.luminance();
+        let delta = luminance - self.luminance_mean;
+        self.luminance_mean += delta / self.samples as f64;
+        self.luminance_m2 += delta * (luminance - self.luminance_mean);
+    }
+
+    /// Combine the statistics of two disjoint sets of samples
+    pub fn merge(&mut self, other: &PixelStats) {
+        let n = self.samples + other.samples;
+        if n == 0 {
+            return;
+        }
+        let (na, nb) = (self.samples as f64, other.samples as f64);
+        let delta = other.luminance_mean - self.luminance_mean;
+        self.luminance_mean += delta * nb / n as f64;
+        self.luminance_m2 += other.luminance_m2 + delta * delta * na * nb / n as f64;
+        self.sum += other.sum;
+        self.samples = n;
+    }
+
+    pub fn mean(&self) -> Color {
+        match self.samples {
+            0 => Color::zero(),
+            n => self.sum / n as f64,
+        }
+    }
+
+    /// Sample variance of the luminance
+    pub fn variance(&self) -> f64 {
+        match self.samples {
+            0 | 1 => f64::INFINITY,
+            n => self.luminance_m2 / (n - 1) as f64,
+        }
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself
+    pub fn relative_error(&self) -> f64 {
+        let std_error = (self.variance() / self.samples as f64).sqrt();
+        if std_error == 0.0 {
+            0.0
+        } else {
+            std_error / self.luminance_mean.abs().max(1e-4)
+        }
+    }
+}
+
+/// Radiance a sample carries to a pixel other than its own, as light tracing does
+#[derive(Copy, Clone, Debug)]
+pub struct Splat {
+    pub x: u32,
+    pub y: u32,
+    pub radiance: Color,
+}
+
+/// Leading bytes of a checkpoint file, bumped whenever the layout changes
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT04";
+/// Bytes of the magic, size and header fields before the pixel records
+const CHECKPOINT_HEADER_SIZE: u64 = 40;
+/// Bytes per pixel: radiance sum, sample count, luminance mean and M2, splatted radiance
+const CHECKPOINT_RECORD_SIZE: u64 = 72;
+
+/// What a checkpoint records besides the samples
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CheckpointHeader {
+    /// Seed the random streams of the samples derive from
+    pub seed: u64,
+    /// Hash of the scene and settings the samples were rendered with
+    pub settings: u64,
+    /// Radius the photon mapper gathered within on the first pass, which resumed renders keep
+    pub photon_radius: Option<f64>,
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64(input: &mut impl Read) -> std::io::Result<f64> {
+    read_u64(input).map(f64::from_bits)
+}
+
+/// Linear radiance framebuffer, row-major with the top row first
+///
+/// Every pixel keeps the statistics of its samples, so passes can be added up before the image
+/// is resolved. Splatted radiance is summed apart and spread over the samples of the whole
+/// image, each of which could have carried it to any pixel.
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<PixelStats>,
+    splats: Vec<Color>,
+    /// Samples taken over all pixels
+    samples: u64,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let len = width as usize * height as usize;
+        Film {
+            width,
+            height,
+            pixels: vec![PixelStats::default(); len],
+            splats: vec![Color::zero(); len],
+            samples: 0,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Largest number of samples any pixel has received
+    pub fn max_samples(&self) -> u64 {
+        self.pixels.iter().map(|p| p.samples).max().unwrap_or(0)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    /// Add a batch of samples to a pixel
+    pub fn add(&mut self, x: u32, y: u32, stats: &PixelStats) {
+        let idx = self.index(x, y);
+        self.pixels[idx].merge(stats);
+        self.samples += stats.samples;
+    }
+
+    pub fn splat(&mut self, splat: &Splat) {
+        let idx = self.index(splat.x, splat.y);
+        self.splats[idx] += splat.radiance;
+    }
+
+    /// Statistics of the samples a pixel has received so far
+    pub fn stats(&self, x: u32, y: u32) -> &PixelStats {
+        &self.pixels[self.index(x, y)]
+    }
+
+    /// Mean radiance of a pixel
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let idx = self.index(x, y);
+        let splat = match self.samples {
+            0 => Color::zero(),
+            n => self.splats[idx] * self.pixels.len() as f64 / n as f64,
+        };
+        self.pixels[idx].mean() + splat
+    }
+
+    /// Write a heatmap of how many samples each pixel received, brightest at the maximum
+    pub fn save_heatmap<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let max = self.max_samples().max(1);
+        let mut img: RgbImage = ImageBuffer::new(self.width, self.height);
+        for (pixel, stats) in img.pixels_mut().zip(&self.pixels) {
+            let t = stats.samples as f64 / max as f64;
+            // black -> blue -> red -> yellow
+            let r = (2.0 * t - 0.5).clamp(0.0, 1.0);
+            let g = (2.0 * t - 1.0).clamp(0.0, 1.0);
+            let b = if t < 0.5 { 2.0 * t } else { 2.0 - 2.0 * t };
+            *pixel = image::Rgb([(255.0 * r) as u8, (255.0 * g) as u8, (255.0 * b) as u8]);
+        }
+        img.save(path)
+            .map_err(|err| format!("Failed to save {}: {}", path.display(), err))
+    }
+
+    fn pixels(&self) -> impl Iterator<Item = Color> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.pixel(x, y)))
+    }
+
+    /// Write the film, picking the format from the file extension
+    ///
+    /// `.exr` and `.pfm` keep the linear radiance, anything else goes through `tonemap` and is
+    /// quantised to 8 bits.
+    pub fn save<P: AsRef<Path>>(&self, path: P, tonemap: &ToneMap) -> Result<(), String> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let result = match extension.as_deref() {
+            Some("exr") => self.save_exr(path),
+            Some("pfm") => self.save_pfm(path),
+            _ => self.save_ldr(path, tonemap),
+        };
+        result.map_err(|err| format!("Failed to save {}: {}", path.display(), err))
+    }
+
+    fn save_ldr(&self, path: &Path, tonemap: &ToneMap) -> Result<(), String> {
+        let mut img: RgbImage = ImageBuffer::new(self.width, self.height);
+        for (pixel, color) in img.pixels_mut().zip(self.pixels()) {
+            *pixel = image::Rgb(tonemap.quantise(color));
+        }
+        img.save(path).map_err(|err| err.to_string())
+    }
+
+    fn save_exr(&self, path: &Path) -> Result<(), String> {
+        let data = self
+            .pixels()
+            .flat_map(|c| [c.x as f32, c.y as f32, c.z as f32])
+            .collect();
+        let img: Rgb32FImage = ImageBuffer::from_raw(self.width, self.height, data).unwrap();
+        img.save_with_format(path, image::ImageFormat::OpenExr)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Portable float map: little-endian RGB `f32`, bottom row first
+    fn save_pfm(&self, path: &Path) -> Result<(), String> {
+        let write = || -> std::io::Result<()> {
+            let mut out = BufWriter::new(File::create(path)?);
+            write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+            for y in (0..self.height).rev() {
+                for x in 0..self.width {
+                    let c = self.pixel(x, y);
+                    for v in [c.x, c.y, c.z] {
+                        out.write_all(&(v as f32).to_le_bytes())?;
+                    }
+                }
+            }
+            out.flush()
+        };
+        write().map_err(|err| err.to_string())
+    }
+
+    /// Write the accumulated samples of every pixel, together with `header`, so the render can
+    /// be resumed later
+    ///
+    /// The file is written next to `path` and renamed over it, so an interrupted write never
+    /// clobbers the previous checkpoint.
+    pub fn save_checkpoint<P: AsRef<Path>>(
+        &self,
+        path: P,
+        header: &CheckpointHeader,
+    ) -> Result<(), String> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let write = || -> std::io::Result<()> {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            out.write_all(CHECKPOINT_MAGIC)?;
+            out.write_all(&self.width.to_le_bytes())?;
+            out.write_all(&self.height.to_le_bytes())?;
+            out.write_all(&header.seed.to_le_bytes())?;
+            out.write_all(&header.settings.to_le_bytes())?;
+            // NaN stands for no radius
+            let photon_radius = header.photon_radius.unwrap_or(f64::NAN);
+            out.write_all(&photon_radius.to_le_bytes())?;
+            for (p, splat) in self.pixels.iter().zip(&self.splats) {
+                for v in [p.sum.x, p.sum.y, p.sum.z] {
+                    out.write_all(&v.to_le_bytes())?;
+                }
+                out.write_all(&p.samples.to_le_bytes())?;
+                out.write_all(&p.luminance_mean.to_le_bytes())?;
+                out.write_all(&p.luminance_m2.to_le_bytes())?;
+                for v in [splat.x, splat.y, splat.z] {
+                    out.write_all(&v.to_le_bytes())?;
+                }
+            }
+            out.flush()?;
+            drop(out);
+            fs::rename(&tmp, path)
+        };
+        write().map_err(|err| format!("Failed to save {}: {}", path.display(), err))
+    }
+
+    /// Read a checkpoint written by [`Film::save_checkpoint`], returning the film and its header
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<(Film, CheckpointHeader), String> {
+        let path = path.as_ref();
+        let read = || -> std::io::Result<Option<(Film, CheckpointHeader)>> {
+            let mut input = BufReader::new(File::open(path)?);
+            let mut magic = [0u8; 8];
+            input.read_exact(&mut magic)?;
+            if &magic != CHECKPOINT_MAGIC {
+                return Ok(None);
+            }
+            let width = read_u32(&mut input)?;
+            let height = read_u32(&mut input)?;
+            let header = CheckpointHeader {
+                seed: read_u64(&mut input)?,
+                settings: read_u64(&mut input)?,
+                photon_radius: Some(read_f64(&mut input)?).filter(|r| !r.is_nan()),
+            };
+
+            // check the size against the file before trusting it with an allocation
+            let records = input
+                .get_ref()
+                .metadata()?
+                .len()
+                .checked_sub(CHECKPOINT_HEADER_SIZE);
+            let expected = (width as u64)
+                .checked_mul(height as u64)
+                .and_then(|pixels| pixels.checked_mul(CHECKPOINT_RECORD_SIZE));
+            if expected.is_none() || expected != records {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}x{} pixels do not match the file size", width, height),
+                ));
+            }
+            let mut film = Film::new(width, height);
+            for (p, splat) in film.pixels.iter_mut().zip(&mut film.splats) {
+                p.sum = Color::new(
+                    read_f64(&mut input)?,
+                    read_f64(&mut input)?,
+                    read_f64(&mut input)?,
+                );
+                p.samples = read_u64(&mut input)?;
+                p.luminance_mean = read_f64(&mut input)?;
+                p.luminance_m2 = read_f64(&mut input)?;
+                *splat = Color::new(
+                    read_f64(&mut input)?,
+                    read_f64(&mut input)?,
+                    read_f64(&mut input)?,
+                );
+                film.samples += p.samples;
+            }
+            Ok(Some((film, header)))
+        };
+        match read() {
+            Ok(Some(checkpoint)) => Ok(checkpoint),
+            Ok(None) => Err(format!("{}: not a checkpoint file", path.display())),
+            Err(err) => Err(format!("Failed to read {}: {}", path.display(), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let mut film = Film::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                let mut stats = PixelStats::default();
+                for k in 0..=x + y {
+                    stats.push(Color::new(x as f64, y as f64, 0.25 * k as f64));
+                }
+                film.add(x, y, &stats);
+            }
+        }
+        film.splat(&Splat {
+            x: 2,
+            y: 1,
+            radiance: Color::new(0.5, 1.5, 2.5),
+        });
+        let header = CheckpointHeader {
+            seed: 0x0123_4567_89ab_cdef,
+            settings: 42,
+            photon_radius: Some(0.125),
+        };
+
+        let path = std::env::temp_dir().join(format!("film-{}.ckpt", std::process::id()));
+        film.save_checkpoint(&path, &header).unwrap();
+        let loaded = Film::load_checkpoint(&path);
+        fs::remove_file(&path).unwrap();
+        let (loaded, loaded_header) = loaded.unwrap();
+
+        assert_eq!(loaded_header, header);
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        assert_eq!(loaded.max_samples(), film.max_samples());
+        for y in 0..2 {
+            for x in 0..3 {
+                let (a, b) = (film.stats(x, y), loaded.stats(x, y));
+                assert_eq!((a.sum, a.samples), (b.sum, b.samples));
+                assert_eq!(a.variance(), b.variance());
+                assert_eq!(film.pixel(x, y), loaded.pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn checkpoint_without_photon_radius() {
+        let header = CheckpointHeader {
+            seed: 7,
+            settings: 0,
+            photon_radius: None,
+        };
+        let path = std::env::temp_dir().join(format!("film-{}-none.ckpt", std::process::id()));
+        Film::new(1, 1).save_checkpoint(&path, &header).unwrap();
+        let loaded = Film::load_checkpoint(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().1, header);
+    }
+
+    #[test]
+    fn rejects_sizes_the_file_does_not_hold() {
+        let path = std::env::temp_dir().join(format!("film-{}-size.ckpt", std::process::id()));
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend([0; 24]);
+        fs::write(&path, &bytes).unwrap();
+        let loaded = Film::load_checkpoint(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(err) if err.ends_with("do not match the file size")));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("film-{}.txt", std::process::id()));
+        fs::write(&path, "not a checkpoint at all").unwrap();
+        let loaded = Film::load_checkpoint(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(err) if err.ends_with("not a checkpoint file")));
+    }
+}
@@ -0,0 +1,291 @@
+use clap::ValueEnum;
+
+use crate::{
+    cfg::Config,
+    geom::ray::Ray,
+    hit::hittable::{HitRecord, Hittable},
+};
+
+use super::{
+    bdpt::BidirectionalPathTracer,
+    color::{light_pdf, power_heuristic, ray_color, ray_color_mis, sample_light, Color},
+    film::Splat,
+    mat::ScatterRecord,
+    pdf::PDF,
+    photon::PhotonMapper,
+    renderer::Scene,
+    sampler::Sampler,
+};
+
+/// Rendering algorithm
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum IntegratorName {
+    /// Path tracing with next-event estimation and multiple importance sampling
+    Path,
+    /// Bidirectional path tracing, joining paths from the camera and from the lights
+    Bdpt,
+    /// Photon mapping: indirect light gathered from photons traced from the lights once
+    Photon,
+    /// Photon mapping tracing new photons every pass, gathering them over a shrinking radius
+    ProgressivePhoton,
+    /// Path tracing scattering by an even mixture of light and material sampling
+    Mixture,
+    /// Path tracing with `Material::scatter` alone, as in "Ray Tracing in One Weekend"
+    Weekend,
+    /// Emission and light arriving straight from the lights, seen through specular surfaces
+    Direct,
+    /// Fraction of the hemisphere above the first hit that is not occluded
+    Ao,
+}
+
+/// Estimates the radiance arriving along camera rays
+pub trait Integrator: Sync {
+    /// Radiance arriving along `ray`
+    ///
+    /// Light that the sample finds for other pixels, as light tracing does, goes to `splats`.
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color;
+
+    /// Get ready for pass `pass` over the image, counting the passes of resumed renders too
+    fn begin_pass(&self, _scene: &Scene, _pass: u64) {}
+
+    /// Radius photons are gathered within on the first pass, once the integrator has settled it
+    fn photon_radius(&self) -> Option<f64> {
+        None
+    }
+}
+
+pub fn integrator_select(cfg: &Config) -> Box<dyn Integrator> {
+    match cfg.integrator {
+        IntegratorName::Path => Box::new(PathTracer {
+            max_depth: cfg.max_depth,
+            rr_min_bounces: cfg.rr_min_bounces,
+        }),
+        IntegratorName::Bdpt => Box::new(BidirectionalPathTracer {
+            max_depth: cfg.max_depth,
+        }),
+        IntegratorName::Photon | IntegratorName::ProgressivePhoton => {
+            let progressive = cfg.integrator == IntegratorName::ProgressivePhoton;
+            Box::new(PhotonMapper::new(
+                cfg.max_depth,
+                cfg.rr_min_bounces,
+                cfg.photons,
+                cfg.photon_radius,
+                progressive.then_some(cfg.ppm_alpha),
+                cfg.seed(),
+            ))
+        }
+        IntegratorName::Mixture => Box::new(MixturePathTracer {
+            max_depth: cfg.max_depth,
+        }),
+        IntegratorName::Weekend => Box::new(WeekendPathTracer {
+            max_depth: cfg.max_depth,
+        }),
+        IntegratorName::Direct => Box::new(DirectLighting {
+            max_depth: cfg.max_depth,
+        }),
+        IntegratorName::Ao => Box::new(AmbientOcclusion {
+            distance: cfg.ao_distance.unwrap_or(f64::INFINITY),
+        }),
+    }
+}
+
+pub struct PathTracer {
+    pub max_depth: u64,
+    pub rr_min_bounces: u64,
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        ray_color_mis(
+            ray,
+            scene.background,
+            scene.world,
+            scene.lights,
+            self.max_depth,
+            self.rr_min_bounces,
+            rng,
+        )
+    }
+}
+
+pub struct MixturePathTracer {
+    pub max_depth: u64,
+}
+
+impl Integrator for MixturePathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        ray_color(
+            ray,
+            scene.background,
+            scene.world,
+            scene.lights,
+            self.max_depth,
+            rng,
+        )
+    }
+}
+
+pub struct WeekendPathTracer {
+    pub max_depth: u64,
+}
+
+impl WeekendPathTracer {
+    fn ray_color(
+        ray: &Ray,
+        background: Color,
+        world: &dyn Hittable,
+        depth: u64,
+        rng: &mut Sampler,
+    ) -> Color {
+        if depth == 0 {
+            return Color::zero();
+        }
+        let Some(rec) = world.hit(ray, 0.00001, f64::INFINITY, rng) else {
+            return background;
+        };
+        let emitted = rec.material.emitted(&rec);
+        match rec.material.scatter(ray, &rec, rng) {
+            Some((attenuation, scattered)) => {
+                emitted
+                    + attenuation
+                        * WeekendPathTracer::ray_color(
+                            &scattered,
+                            background,
+                            world,
+                            depth - 1,
+                            rng,
+                        )
+            }
+            None => emitted,
+        }
+    }
+}
+
+impl Integrator for WeekendPathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        WeekendPathTracer::ray_color(ray, scene.background, scene.world, self.max_depth, rng)
+    }
+}
+
+/// Light reaching `rec` straight from the emitters or the background, from a light sample and a
+/// sample of the material's `pdf` combined by multiple importance sampling
+pub fn direct_light(
+    ray: &Ray,
+    rec: &HitRecord,
+    pdf: &PDF,
+    scene: &Scene,
+    rng: &mut Sampler,
+) -> Color {
+    let direct = sample_light(ray, rec, pdf, scene.world, scene.lights, rng);
+    let scattered = Ray::new(rec.position, pdf.generate(rng), ray.time());
+    let pdf_value = pdf.value(scattered.direction());
+    let scattering = if pdf_value > 0.0 {
+        rec.material.scattering(ray, rec, &scattered) / pdf_value
+    } else {
+        Color::zero()
+    };
+    let incoming = match scene.world.hit(&scattered, 0.00001, f64::INFINITY, rng) {
+        Some(hit) => {
+            hit.material.emitted(&hit)
+                * power_heuristic(pdf_value, light_pdf(scene.lights, &scattered, hit.t, rng))
+        }
+        None => scene.background,
+    };
+    direct + scattering * incoming
+}
+
+/// Light that reached the first diffuse surface along a camera ray in one bounce at most,
+/// following up to `max_depth` specular bounces before it
+pub struct DirectLighting {
+    pub max_depth: u64,
+}
+
+impl Integrator for DirectLighting {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        let mut radiance = Color::zero();
+        let mut throughput = Color::ones();
+        let mut ray = *ray;
+        for _ in 0..self.max_depth {
+            let Some(rec) = scene.world.hit(&ray, 0.00001, f64::INFINITY, rng) else {
+                return radiance + throughput * scene.background;
+            };
+            radiance += throughput * rec.material.emitted(&rec);
+            let (pdf, attenuation) = match rec.material.scatter_monte_carlo(&ray, &rec, rng) {
+                None => break,
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => {
+                    throughput = throughput * attenuation;
+                    ray = specular_ray;
+                    continue;
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation }) => (pdf, attenuation),
+            };
+
+            radiance += throughput * attenuation * direct_light(&ray, &rec, &pdf, scene, rng);
+            break;
+        }
+        radiance
+    }
+}
+
+/// White where the hemisphere around the first hit is open up to `distance`, darker the more
+/// of it is occluded
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        let Some(rec) = scene.world.hit(ray, 0.00001, f64::INFINITY, rng) else {
+            return Color::ones();
+        };
+        let probe = Ray::new(
+            rec.position,
+            PDF::cosine_pdf(rec.normal).generate(rng),
+            ray.time(),
+        );
+        let distance = self.distance / probe.direction().length();
+        match scene.world.hit(&probe, 0.00001, distance, rng) {
+            Some(_) => Color::zero(),
+            None => Color::ones(),
+        }
+    }
+}
@@ -1,11 +1,13 @@
+use std::sync::Arc;
+
 use rand::Rng;
 
 use crate::geom::ray::Ray;
 use crate::geom::vec3::Vec3;
 use crate::hit::hittable::HitRecord;
 use crate::render::color::Color;
-use crate::render::onb::ONB;
-use crate::render::pdf::PDF;
+use crate::render::pdf::{henyey_greenstein, PDF};
+use crate::render::sampler::Sampler;
 use crate::render::texture::Texture;
 use crate::utils::PI;
 
@@ -20,22 +22,73 @@ pub enum ScatterRecord<'a> {
     },
 }
 
-pub trait Material: Sync {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+pub trait Material: Send + Sync {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _rng: &mut Sampler) -> Option<(Color, Ray)> {
         None
     }
 
-    fn scatter_monte_carlo(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter_monte_carlo(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
         None
     }
 
-    fn emitted(&self, rec: &HitRecord) -> Color {
+    fn emitted(&self, _rec: &HitRecord) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
 
-    fn scatter_pdf(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
+    /// Density with which the material's own lobe samples `ray_out`
+    fn scatter_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _ray_out: &Ray) -> f64 {
         0.0
     }
+
+    /// BSDF times the cosine of `ray_out`, per channel, tinted further by the attenuation of the
+    /// scatter record
+    ///
+    /// Defaults to `scatter_pdf` for materials whose lobe samples exactly in proportion to
+    /// their scattering and whose colour is all in the attenuation.
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> Color {
+        self.scatter_pdf(r_in, rec, ray_out) * Color::ones()
+    }
+
+    /// Whether this is the phase function of a participating medium rather than a surface
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+}
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        (**self).scatter(r_in, rec, rng)
+    }
+
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
+        (**self).scatter_monte_carlo(r_in, rec, rng)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        (**self).emitted(rec)
+    }
+
+    fn scatter_pdf(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
+        (**self).scatter_pdf(r_in, rec, ray_out)
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> Color {
+        (**self).scattering(r_in, rec, ray_out)
+    }
+
+    fn is_volumetric(&self) -> bool {
+        (**self).is_volumetric()
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -50,29 +103,31 @@ impl<T: Texture> Lambertian<T> {
 }
 
 impl<T: Texture> Material for Lambertian<T> {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_direction = rec.normal + Vec3::random_in_unit_sphere().unit();
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        let mut scatter_direction = rec.normal + Vec3::random_in_unit_sphere(rng).unit();
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
         let scattered = Ray::new(rec.position, scatter_direction, r_in.time());
 
-        Some((
-            self.albedo.texture_map(rec.u, rec.v, &rec.position),
-            scattered,
-        ))
+        Some((self.albedo.value(rec), scattered))
     }
 
-    fn scatter_monte_carlo(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter_monte_carlo(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
         let rec = ScatterRecord::Scatter {
             pdf: PDF::cosine_pdf(rec.normal),
-            attenuation: self.albedo.texture_map(rec.u, rec.v, &rec.position),
+            attenuation: self.albedo.value(rec),
         };
         Some(rec)
     }
 
-    fn scatter_pdf(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
+    fn scatter_pdf(&self, _r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
         rec.normal.dot(ray_out.direction().unit()).max(0.0) / PI
     }
 }
@@ -90,11 +145,11 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
         let reflected = Vec3::reflect(r_in.direction(), rec.normal).unit();
         let scattered = Ray::new(
             rec.position,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(rng),
             r_in.time(),
         );
 
@@ -105,11 +160,16 @@ impl Material for Metal {
         }
     }
 
-    fn scatter_monte_carlo(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
         let reflected = Vec3::reflect(r_in.direction(), rec.normal).unit();
         let scattered = Ray::new(
             rec.position,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(rng),
             r_in.time(),
         );
 
@@ -142,7 +202,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
         let refraction_ratio = if rec.front_face {
             1.0 / self.ir
         } else {
@@ -154,7 +214,6 @@ impl Material for Dielectric {
         let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let mut rng = rand::thread_rng();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let will_reflect = rng.gen::<f64>() < Self::reflectance(cos_theta, refraction_ratio);
 
@@ -168,7 +227,12 @@ impl Material for Dielectric {
         Some((Color::new(1.0, 1.0, 1.0), scattered))
     }
 
-    fn scatter_monte_carlo(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
         let attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if rec.front_face {
             1.0 / self.ir
@@ -181,7 +245,6 @@ impl Material for Dielectric {
         let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let mut rng = rand::thread_rng();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let will_reflect = rng.gen::<f64>() < Self::reflectance(cos_theta, refraction_ratio);
 
@@ -214,15 +277,100 @@ impl<T: Texture> DiffuseLight<T> {
 }
 
 impl<T: Texture> Material for DiffuseLight<T> {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _rng: &mut Sampler) -> Option<(Color, Ray)> {
         None
     }
 
     fn emitted(&self, rec: &HitRecord) -> Color {
         if rec.front_face {
-            self.emit.texture_map(rec.u, rec.v, &rec.position)
+            self.emit.value(rec)
         } else {
             Color::new(0.0, 0.0, 0.0)
         }
     }
 }
+
+/// Phase function of a participating medium scattering equally in every direction
+#[derive(Copy, Clone)]
+pub struct Isotropic<T: Texture> {
+    albedo: T,
+}
+
+impl<T: Texture> Isotropic<T> {
+    pub fn new(albedo: T) -> Isotropic<T> {
+        Isotropic { albedo }
+    }
+}
+
+impl<T: Texture> Material for Isotropic<T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        let scattered = Ray::new(rec.position, Vec3::random_unit_vector(rng), r_in.time());
+        Some((self.albedo.value(rec), scattered))
+    }
+
+    fn scatter_monte_carlo(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::uniform_pdf(),
+            attenuation: self.albedo.value(rec),
+        })
+    }
+
+    fn scatter_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _ray_out: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
+
+/// Phase function of a participating medium favouring forward (`g > 0`) or backward (`g < 0`)
+/// scattering, isotropic for `g = 0`
+#[derive(Copy, Clone)]
+pub struct HenyeyGreenstein<T: Texture> {
+    albedo: T,
+    g: f64,
+}
+
+impl<T: Texture> HenyeyGreenstein<T> {
+    pub fn new(albedo: T, g: f64) -> HenyeyGreenstein<T> {
+        HenyeyGreenstein {
+            albedo,
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+}
+
+impl<T: Texture> Material for HenyeyGreenstein<T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        let pdf = PDF::henyey_greenstein_pdf(r_in.direction(), self.g);
+        let scattered = Ray::new(rec.position, pdf.generate(rng), r_in.time());
+        Some((self.albedo.value(rec), scattered))
+    }
+
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::henyey_greenstein_pdf(r_in.direction(), self.g),
+            attenuation: self.albedo.value(rec),
+        })
+    }
+
+    fn scatter_pdf(&self, r_in: &Ray, _rec: &HitRecord, ray_out: &Ray) -> f64 {
+        let cos_theta = r_in.direction().unit().dot(ray_out.direction().unit());
+        henyey_greenstein(cos_theta, self.g)
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,375 @@
+use rand::Rng;
+
+use crate::geom::ray::Ray;
+use crate::geom::vec3::Vec3;
+use crate::hit::hittable::HitRecord;
+use crate::render::color::Color;
+use crate::render::mat::{Material, ScatterRecord};
+use crate::render::onb::ONB;
+use crate::render::pdf::PDF;
+use crate::render::sampler::Sampler;
+use crate::utils::PI;
+
+/// Fresnel reflectance of a dielectric interface, `eta` being the ratio of the index of
+/// refraction on the far side to the one on the incident side
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction `eta + ik`, per channel
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f64, k: f64| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_i * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        0.5 * (r_p + r_s)
+    };
+    Color::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals, in a frame where the macro
+/// surface normal is +z
+#[derive(Copy, Clone)]
+pub struct GGX {
+    alpha: f64,
+}
+
+impl GGX {
+    /// `roughness` is squared into the width of the distribution, so it is perceptually linear
+    pub fn new(roughness: f64) -> GGX {
+        GGX {
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+
+    /// Density of microfacet normals `h` per unit projected area
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    /// Smith masking of the microsurface seen from `w`
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated Smith masking and shadowing for the pair of directions
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `wo`, which `sample_visible` draws from
+    pub fn visible_pdf(&self, wo: Vec3, h: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+
+    /// Sample a normal visible from `wo` (Heitz, "Sampling the GGX Distribution of Visible
+    /// Normals", 2018)
+    pub fn sample_visible(&self, wo: Vec3, rng: &mut Sampler) -> Vec3 {
+        // stretch the view so the distribution becomes a hemisphere
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(vh, t1);
+
+        // sample the projected hemisphere, a disk squashed on the side facing away from `wo`
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).unit()
+    }
+
+    /// Half vector of reflection from `wo` into `wi`, and the BSDF times cosine of the
+    /// microfacets there without Fresnel, zero unless both directions are above the surface
+    pub fn reflection(&self, wo: Vec3, wi: Vec3) -> (Vec3, f64) {
+        let h = (wo + wi).unit();
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (h, 0.0);
+        }
+        (h, self.d(h) * self.g2(wo, wi) / (4.0 * wo.z))
+    }
+
+    /// BSDF times cosine of a rough interface into a dielectric of relative index of
+    /// refraction `eta`, reflecting and refracting
+    pub fn dielectric(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        if wi.z > 0.0 {
+            let (h, microfacets) = self.reflection(wo, wi);
+            return fresnel_dielectric(wo.dot(h), eta) * microfacets;
+        }
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+        let Some(h) = transmission_normal(wo, wi, eta) else {
+            return 0.0;
+        };
+        // the eta^2 of the change of solid angle cancels the 1/eta^2 radiance picks up
+        let denom = wo.dot(h) + eta * wi.dot(h);
+        (1.0 - fresnel_dielectric(wo.dot(h), eta))
+            * self.d(h)
+            * self.g2(wo, wi)
+            * (wo.dot(h) * wi.dot(h)).abs()
+            / (wo.z * denom * denom)
+    }
+}
+
+/// Microfacet normal of refraction from `wo` into `wi`, facing the `wo` side, if neither
+/// direction sees the back of it
+fn transmission_normal(wo: Vec3, wi: Vec3, eta: f64) -> Option<Vec3> {
+    let h = (wo + eta * wi).unit();
+    let h = if h.z < 0.0 { -h } else { h };
+    (wo.dot(h) > 0.0 && wi.dot(h) < 0.0).then_some(h)
+}
+
+/// Directions sampled through GGX visible normals around a shading normal, reflected off a
+/// conductor or, given the relative index of refraction, reflected or refracted by Fresnel
+#[derive(Clone)]
+pub struct MicrofacetLobe {
+    uvw: ONB,
+    wo: Vec3,
+    ggx: GGX,
+    eta: Option<f64>,
+}
+
+impl MicrofacetLobe {
+    /// `wo` points away from the surface, on the side of `normal`
+    pub fn new(normal: Vec3, wo: Vec3, ggx: GGX, eta: Option<f64>) -> MicrofacetLobe {
+        let uvw = ONB::build_from_w(&normal);
+        let wo = uvw.coordinates(&wo.unit());
+        MicrofacetLobe { uvw, wo, ggx, eta }
+    }
+
+    /// The same lobe off a surface of another roughness or relative index of refraction
+    pub fn with_surface(&self, ggx: GGX, eta: Option<f64>) -> MicrofacetLobe {
+        MicrofacetLobe {
+            uvw: self.uvw.clone(),
+            wo: self.wo,
+            ggx,
+            eta,
+        }
+    }
+
+    /// Shading frame, with the normal as w
+    pub fn uvw(&self) -> &ONB {
+        &self.uvw
+    }
+
+    pub fn value(&self, direction: Vec3) -> f64 {
+        let wo = self.wo;
+        let wi = self.uvw.coordinates(&direction.unit());
+        if wi.z > 0.0 {
+            let h = (wo + wi).unit();
+            let reflectance = self
+                .eta
+                .map_or(1.0, |eta| fresnel_dielectric(wo.dot(h), eta));
+            reflectance * self.ggx.visible_pdf(wo, h) / (4.0 * wo.dot(h))
+        } else if let Some(eta) = self.eta {
+            let Some(h) = transmission_normal(wo, wi, eta) else {
+                return 0.0;
+            };
+            let denom = wo.dot(h) + eta * wi.dot(h);
+            let jacobian = eta * eta * wi.dot(h).abs() / (denom * denom);
+            (1.0 - fresnel_dielectric(wo.dot(h), eta)) * self.ggx.visible_pdf(wo, h) * jacobian
+        } else {
+            0.0
+        }
+    }
+
+    pub fn generate(&self, rng: &mut Sampler) -> Vec3 {
+        let h = self.ggx.sample_visible(self.wo, rng);
+        let direction = match self.eta {
+            Some(eta) if rng.gen::<f64>() >= fresnel_dielectric(self.wo.dot(h), eta) => {
+                Vec3::refract(-self.wo, h, 1.0 / eta)
+            }
+            _ => Vec3::reflect(-self.wo, h),
+        };
+        self.uvw.local(&direction)
+    }
+}
+
+/// Draw a direction from a material's own lobe for `Material::scatter`, weighted by the
+/// scattering over its density
+pub fn sample_scatter<M: Material>(
+    material: &M,
+    r_in: &Ray,
+    rec: &HitRecord,
+    rng: &mut Sampler,
+) -> Option<(Color, Ray)> {
+    let Some(ScatterRecord::Scatter { pdf, attenuation }) =
+        material.scatter_monte_carlo(r_in, rec, rng)
+    else {
+        return None;
+    };
+    let scattered = Ray::new(rec.position, pdf.generate(rng), r_in.time());
+    let pdf_value = pdf.value(scattered.direction());
+    if pdf_value <= 0.0 {
+        return None;
+    }
+    let weight = attenuation * material.scattering(r_in, rec, &scattered) / pdf_value;
+    Some((weight, scattered))
+}
+
+/// Incoming and outgoing directions in the shading frame of `rec`, `wo` pointing back along
+/// `r_in`
+pub fn shading_directions(r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> (Vec3, Vec3) {
+    let uvw = ONB::build_from_w(&rec.normal);
+    (
+        uvw.coordinates(&-r_in.direction().unit()),
+        uvw.coordinates(&ray_out.direction().unit()),
+    )
+}
+
+/// Rough metal reflecting through GGX microfacets, coloured by its complex index of refraction
+#[derive(Copy, Clone)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    ggx: GGX,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            ggx: GGX::new(roughness),
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        sample_scatter(self, r_in, rec, rng)
+    }
+
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
+        let lobe = MicrofacetLobe::new(rec.normal, -r_in.direction(), self.ggx, None);
+        // seen from below its shading normal the surface reflects nothing
+        if lobe.wo.z <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::Microfacet(lobe),
+            attenuation: Color::ones(),
+        })
+    }
+
+    fn scatter_pdf(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
+        MicrofacetLobe::new(rec.normal, -r_in.direction(), self.ggx, None)
+            .value(ray_out.direction())
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> Color {
+        let (wo, wi) = shading_directions(r_in, rec, ray_out);
+        let (h, microfacets) = self.ggx.reflection(wo, wi);
+        fresnel_conductor(wo.dot(h), self.eta, self.k) * microfacets
+    }
+}
+
+/// Frosted glass, reflecting and refracting through GGX microfacets
+#[derive(Copy, Clone)]
+pub struct RoughDielectric {
+    ir: f64,
+    ggx: GGX,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {
+            ir,
+            ggx: GGX::new(roughness),
+        }
+    }
+
+    /// Index of refraction across the surface relative to the side `rec` is hit from
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            self.ir
+        } else {
+            1.0 / self.ir
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        sample_scatter(self, r_in, rec, rng)
+    }
+
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
+        let lobe =
+            MicrofacetLobe::new(rec.normal, -r_in.direction(), self.ggx, Some(self.eta(rec)));
+        if lobe.wo.z <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::Microfacet(lobe),
+            attenuation: Color::ones(),
+        })
+    }
+
+    fn scatter_pdf(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
+        MicrofacetLobe::new(rec.normal, -r_in.direction(), self.ggx, Some(self.eta(rec)))
+            .value(ray_out.direction())
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> Color {
+        let (wo, wi) = shading_directions(r_in, rec, ray_out);
+        self.ggx.dielectric(wo, wi, self.eta(rec)) * Color::ones()
+    }
+}
@@ -1,6 +1,15 @@
+pub mod bdpt;
 pub mod color;
+pub mod film;
+pub mod integrator;
 pub mod mat;
+pub mod microfacet;
 pub mod onb;
 pub mod pdf;
 pub mod perlin;
+pub mod photon;
+pub mod principled;
+pub mod renderer;
+pub mod sampler;
 pub mod texture;
+pub mod tonemap;
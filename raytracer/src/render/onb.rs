@@ -1,6 +1,7 @@
 use crate::geom::vec3::Vec3;
 
 // ONB: Orthonormal Basis
+#[derive(Clone)]
 pub struct ONB {
     axis: [Vec3; 3],
 }
@@ -34,4 +35,9 @@ impl ONB {
     pub fn local(&self, a: &Vec3) -> Vec3 {
         a.x * self.u() + a.y * self.v() + a.z * self.w()
     }
+
+    /// Coordinates of `a` in this basis, the inverse of `local`
+    pub fn coordinates(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u()), a.dot(self.v()), a.dot(self.w()))
+    }
 }
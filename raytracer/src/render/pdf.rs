@@ -6,20 +6,61 @@ use crate::{
     utils::PI,
 };
 
-use super::onb::ONB;
+use super::{
+    microfacet::{MicrofacetLobe, GGX},
+    onb::ONB,
+    sampler::Sampler,
+};
+
+/// Henyey-Greenstein phase function for the cosine between the directions of travel before and
+/// after scattering
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+/// Density of the cosine-weighted directions around `uvw.w()`
+fn cosine_value(uvw: &ONB, direction: Vec3) -> f64 {
+    let cosine = direction.unit().dot(uvw.w());
+    if cosine > 0.0 {
+        // importance sampling
+        cosine / PI
+    } else {
+        0.0
+    }
+}
 
 pub enum PDF<'a> {
     Cosine {
         uvw: ONB,
     },
+    /// Every direction equally likely
+    Uniform,
+    /// Henyey-Greenstein lobe around the direction of travel, forward for positive `g`
+    HenyeyGreenstein {
+        uvw: ONB,
+        g: f64,
+    },
+    /// GGX visible normals, reflected or refracted
+    Microfacet(MicrofacetLobe),
     Hittable {
         origin: Point3,
-        hittable: &'a Box<dyn Hittable>,
+        hittable: &'a dyn Hittable,
     },
     Mixture {
         p0: &'a PDF<'a>,
         p1: &'a PDF<'a>,
     },
+    /// Mixture of a cosine lobe and microfacet lobes, all in the shading frame of `frame`,
+    /// picking each with probability its weight, the weights summing to 1
+    ///
+    /// The microfacet lobes are given by their weight, roughness and relative index of
+    /// refraction, so that they share one frame and building the mixture does not allocate.
+    Lobes {
+        frame: MicrofacetLobe,
+        diffuse: f64,
+        microfacets: [(f64, GGX, Option<f64>); 3],
+    },
 }
 
 impl<'a> PDF<'a> {
@@ -29,7 +70,18 @@ impl<'a> PDF<'a> {
         }
     }
 
-    pub fn hittable_pdf(origin: Point3, hittable: &'a Box<dyn Hittable>) -> PDF<'a> {
+    pub fn uniform_pdf() -> PDF<'a> {
+        PDF::Uniform
+    }
+
+    pub fn henyey_greenstein_pdf(direction: Vec3, g: f64) -> PDF<'a> {
+        PDF::HenyeyGreenstein {
+            uvw: ONB::build_from_w(&direction),
+            g,
+        }
+    }
+
+    pub fn hittable_pdf(origin: Point3, hittable: &'a dyn Hittable) -> PDF<'a> {
         PDF::Hittable { origin, hittable }
     }
 
@@ -37,32 +89,111 @@ impl<'a> PDF<'a> {
         PDF::Mixture { p0, p1 }
     }
 
+    /// Mix a cosine lobe and `microfacets` in the frame of `frame` in proportion to their
+    /// weights
+    ///
+    /// Gives `None` when no weight is positive, as there is nothing to draw from.
+    pub fn lobes_pdf(
+        frame: MicrofacetLobe,
+        diffuse: f64,
+        microfacets: [(f64, GGX, Option<f64>); 3],
+    ) -> Option<PDF<'a>> {
+        let total = microfacets
+            .iter()
+            .fold(diffuse.max(0.0), |total, (w, ..)| total + w.max(0.0));
+        if !(total > 0.0 && total.is_finite()) {
+            return None;
+        }
+        Some(PDF::Lobes {
+            frame,
+            diffuse: diffuse.max(0.0) / total,
+            microfacets: microfacets.map(|(w, ggx, eta)| (w.max(0.0) / total, ggx, eta)),
+        })
+    }
+
     pub fn value(&self, direction: Vec3) -> f64 {
         match self {
-            PDF::Cosine { uvw } => {
-                let cosine = direction.unit().dot(uvw.w());
-                if cosine > 0.0 {
-                    // importance sampling
-                    cosine / PI
-                } else {
-                    0.0
-                }
+            PDF::Cosine { uvw } => cosine_value(uvw, direction),
+            PDF::Uniform => 1.0 / (4.0 * PI),
+            PDF::HenyeyGreenstein { uvw, g } => {
+                henyey_greenstein(direction.unit().dot(uvw.w()), *g)
             }
+            PDF::Microfacet(lobe) => lobe.value(direction),
             PDF::Hittable { origin, hittable } => hittable.pdf_value(*origin, direction),
             PDF::Mixture { p0, p1 } => 0.5 * p0.value(direction) + 0.5 * p1.value(direction),
+            PDF::Lobes {
+                frame,
+                diffuse,
+                microfacets,
+            } => {
+                let diffuse =
+                    (*diffuse > 0.0).then(|| diffuse * cosine_value(frame.uvw(), direction));
+                diffuse
+                    .into_iter()
+                    .chain(
+                        microfacets
+                            .iter()
+                            .filter(|(w, ..)| *w > 0.0)
+                            .map(|(w, ggx, eta)| {
+                                w * frame.with_surface(*ggx, *eta).value(direction)
+                            }),
+                    )
+                    .sum()
+            }
         }
     }
 
-    pub fn generate(&self) -> Vec3 {
+    pub fn generate(&self, rng: &mut Sampler) -> Vec3 {
         match self {
-            PDF::Cosine { uvw } => uvw.local(&Vec3::random_cos_direction()),
-            PDF::Hittable { origin, hittable } => hittable.random(*origin),
+            PDF::Cosine { uvw } => uvw.local(&Vec3::random_cos_direction(rng)),
+            PDF::Uniform => Vec3::random_unit_vector(rng),
+            PDF::HenyeyGreenstein { uvw, g } => {
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * rng.gen::<f64>()
+                } else {
+                    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rng.gen::<f64>());
+                    ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f64>();
+                uvw.local(&Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ))
+            }
+            PDF::Microfacet(lobe) => lobe.generate(rng),
+            PDF::Hittable { origin, hittable } => hittable.random(*origin, rng),
             PDF::Mixture { p0, p1 } => {
-                let mut rng = rand::thread_rng();
                 if rng.gen::<bool>() {
-                    p0.generate()
+                    p0.generate(rng)
+                } else {
+                    p1.generate(rng)
+                }
+            }
+            PDF::Lobes {
+                frame,
+                diffuse,
+                microfacets,
+            } => {
+                // one draw picks the lobe, so only that one is sampled
+                let mut x = rng.gen::<f64>() - diffuse;
+                let mut weighted = microfacets.iter().filter(|(w, ..)| *w > 0.0);
+                let picked = if x < 0.0 {
+                    None
                 } else {
-                    p1.generate()
+                    weighted
+                        .clone()
+                        .find(|(w, ..)| {
+                            x -= w;
+                            x < 0.0
+                        })
+                        // rounding can leave `x` just past the last weight
+                        .or_else(|| weighted.next_back())
+                };
+                match picked {
+                    Some((_, ggx, eta)) => frame.with_surface(*ggx, *eta).generate(rng),
+                    None => frame.uvw().local(&Vec3::random_cos_direction(rng)),
                 }
             }
         }
@@ -1,3 +1,4 @@
+#![allow(clippy::needless_range_loop)]
 use crate::geom::vec3::{Point3, Vec3};
 use rand::Rng;
 
@@ -29,9 +30,8 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    pub fn new() -> Perlin {
-        fn permute(mut v: Vec<usize>, n: usize) -> Vec<usize> {
-            let mut rng = rand::thread_rng();
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Perlin {
+        fn permute<R: Rng + ?Sized>(mut v: Vec<usize>, n: usize, rng: &mut R) -> Vec<usize> {
             for i in (0..n).rev() {
                 let target = rng.gen_range(0..=i);
                 v.swap(i, target);
@@ -41,11 +41,11 @@ impl Perlin {
 
         Perlin {
             rd_vec: (0..256)
-                .map(|_| Vec3::random_in_unit_sphere())
+                .map(|_| Vec3::random_in_unit_sphere(rng))
                 .collect::<Vec<_>>(),
-            perm_x: permute((0..256).collect::<Vec<_>>(), 256),
-            perm_y: permute((0..256).collect::<Vec<_>>(), 256),
-            perm_z: permute((0..256).collect::<Vec<_>>(), 256),
+            perm_x: permute((0..256).collect::<Vec<_>>(), 256, rng),
+            perm_y: permute((0..256).collect::<Vec<_>>(), 256, rng),
+            perm_z: permute((0..256).collect::<Vec<_>>(), 256, rng),
         }
     }
 
@@ -0,0 +1,408 @@
+use std::sync::RwLock;
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    geom::{
+        ray::Ray,
+        vec3::{Point3, Vec3},
+    },
+    hit::hittable::HitRecord,
+    utils::PI,
+};
+
+use super::{
+    color::{light_pdf, power_heuristic, sample_light, Color},
+    film::Splat,
+    integrator::{direct_light, Integrator},
+    mat::ScatterRecord,
+    pdf::PDF,
+    renderer::Scene,
+    sampler::{photon_sampler, Sampler},
+};
+
+/// Photons traced from one random stream
+const PHOTON_BATCH: usize = 4096;
+
+/// Light carried to a surface by one photon
+#[derive(Copy, Clone)]
+pub struct Photon {
+    pub position: Point3,
+    /// Direction the photon travelled in, of unit length
+    pub direction: Vec3,
+    pub power: Color,
+}
+
+/// Photons in a kd-tree, to find those near a point
+///
+/// The tree is implicit in the order of `photons`: every range of them is split at its middle
+/// photon, along the axis `axes` holds for it, into the photons before and those after.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+/// Extent of the box around `photons`
+fn extent(photons: &[Photon]) -> Vec3 {
+    let (min, max) = photons.iter().fold(
+        (
+            Vec3::ones() * f64::INFINITY,
+            Vec3::ones() * f64::NEG_INFINITY,
+        ),
+        |(min, max), photon| {
+            let p = photon.position;
+            (
+                Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        },
+    );
+    max - min
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.is_empty() {
+        return;
+    }
+    // split along the axis the photons spread furthest over
+    let extent = extent(photons);
+    let axis = (0..3)
+        .max_by(|&a, &b| extent.get(a).total_cmp(&extent.get(b)))
+        .unwrap();
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        a.position.get(axis).total_cmp(&b.position.get(axis))
+    });
+    axes[mid] = axis as u8;
+    let (before, after) = photons.split_at_mut(mid);
+    let (axes_before, axes_after) = axes.split_at_mut(mid);
+    rayon::join(
+        || build(before, axes_before),
+        || build(&mut after[1..], &mut axes_after[1..]),
+    );
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    /// Length of the diagonal of the box around the photons, zero if there are none
+    pub fn size(&self) -> f64 {
+        if self.photons.is_empty() {
+            return 0.0;
+        }
+        extent(&self.photons).length()
+    }
+
+    /// Call `f` on every photon within `radius` of `point`
+    pub fn for_each_within<F: FnMut(&Photon)>(&self, point: Point3, radius: f64, mut f: F) {
+        self.search(0, self.photons.len(), point, radius * radius, &mut f);
+    }
+
+    fn search<F: FnMut(&Photon)>(
+        &self,
+        lo: usize,
+        hi: usize,
+        point: Point3,
+        radius_squared: f64,
+        f: &mut F,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        if (photon.position - point).squared_length() <= radius_squared {
+            f(photon);
+        }
+        let axis = self.axes[mid] as usize;
+        let offset = point.get(axis) - photon.position.get(axis);
+        let (near, far) = if offset < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(near.0, near.1, point, radius_squared, f);
+        if offset * offset <= radius_squared {
+            self.search(far.0, far.1, point, radius_squared, f);
+        }
+    }
+}
+
+/// The photons of one pass and the radius they are gathered over
+struct PhotonPass {
+    map: PhotonMap,
+    radius: f64,
+    /// Radius of the first pass
+    base_radius: f64,
+}
+
+/// Photon mapping: photons traced from the lights are stored where they land on surfaces, and
+/// the light they bring is estimated from their density around the first diffuse surface
+/// camera rays reach, after following specular bounces and media
+///
+/// Light straight from the lights is sampled there instead, so only photons that bounced at
+/// least once are stored. With `alpha`, the map is traced anew every pass and gathered over a
+/// radius that shrinks so that the passes average out to the exact image, as in Knaus and
+/// Zwicker's "Progressive Photon Mapping: A Probabilistic Approach". Photons only start on lights
+/// that can sample their surface.
+pub struct PhotonMapper {
+    max_depth: u64,
+    rr_min_bounces: u64,
+    photons: usize,
+    radius: Option<f64>,
+    alpha: Option<f64>,
+    seed: u64,
+    pass: RwLock<Option<PhotonPass>>,
+}
+
+impl PhotonMapper {
+    pub fn new(
+        max_depth: u64,
+        rr_min_bounces: u64,
+        photons: usize,
+        radius: Option<f64>,
+        alpha: Option<f64>,
+        seed: u64,
+    ) -> PhotonMapper {
+        PhotonMapper {
+            max_depth,
+            rr_min_bounces,
+            photons,
+            radius,
+            alpha,
+            seed,
+            pass: RwLock::new(None),
+        }
+    }
+
+    /// Gathering radius of pass `pass`, for a first pass gathering within `base_radius`
+    fn radius(&self, base_radius: f64, pass: u64) -> f64 {
+        let Some(alpha) = self.alpha else {
+            return base_radius;
+        };
+        let radius_squared = (1..=pass).fold(base_radius * base_radius, |r2, i| {
+            r2 * (i as f64 + alpha) / (i as f64 + 1.0)
+        });
+        radius_squared.sqrt()
+    }
+
+    /// Trace a batch of `count` photons, each carrying a `1 / total` share of the light
+    fn trace_batch(
+        &self,
+        scene: &Scene,
+        count: usize,
+        total: usize,
+        rng: &mut Sampler,
+    ) -> Vec<Photon> {
+        let mut photons = Vec::new();
+        for _ in 0..count {
+            let Some((rec, pdf_position)) = scene.lights.sample_surface(rng) else {
+                continue;
+            };
+            let emitted = rec.material.emitted(&rec);
+            if pdf_position <= 0.0 || emitted.luminance() <= 0.0 {
+                continue;
+            }
+            // cosine-weighted emission leaves pi over the density of the point
+            let mut power = emitted * PI / (pdf_position * total as f64);
+            // photons leave while the shutter is open, so moving objects blur in the map too
+            let time = scene.camera.sample_time(rng);
+            let mut ray = Ray::new(
+                rec.position,
+                PDF::cosine_pdf(rec.normal).generate(rng),
+                time,
+            );
+
+            for bounce in 0..self.max_depth {
+                let Some(rec) = scene.world.hit(&ray, 0.00001, f64::INFINITY, rng) else {
+                    break;
+                };
+                match rec.material.scatter_monte_carlo(&ray, &rec, rng) {
+                    None => break,
+                    Some(ScatterRecord::Specular {
+                        specular_ray,
+                        attenuation,
+                    }) => {
+                        power = power * attenuation;
+                        ray = specular_ray;
+                    }
+                    Some(ScatterRecord::Scatter { pdf, attenuation }) => {
+                        if bounce > 0 && !rec.material.is_volumetric() {
+                            photons.push(Photon {
+                                position: rec.position,
+                                direction: ray.direction().unit(),
+                                power,
+                            });
+                        }
+                        let scattered = Ray::new(rec.position, pdf.generate(rng), ray.time());
+                        let pdf_value = pdf.value(scattered.direction());
+                        if pdf_value <= 0.0 {
+                            break;
+                        }
+                        let mut scattered_power =
+                            power * attenuation * rec.material.scattering(&ray, &rec, &scattered)
+                                / pdf_value;
+                        if bounce + 1 >= self.rr_min_bounces {
+                            // survivors keep about the power they had, so photons stay alike
+                            let survival =
+                                (scattered_power.luminance() / power.luminance()).min(1.0);
+                            if rng.gen::<f64>() >= survival {
+                                break;
+                            }
+                            scattered_power = scattered_power / survival;
+                        }
+                        power = scattered_power;
+                        ray = scattered;
+                    }
+                }
+            }
+        }
+        photons
+    }
+
+    /// Trace the photons of pass `pass` into a map
+    fn trace(&self, scene: &Scene, pass: u64) -> PhotonMap {
+        let photons = (0..self.photons.div_ceil(PHOTON_BATCH))
+            .into_par_iter()
+            .flat_map_iter(|batch| {
+                let mut rng = photon_sampler(self.seed, pass, batch as u64);
+                let count = PHOTON_BATCH.min(self.photons - batch * PHOTON_BATCH);
+                self.trace_batch(scene, count, self.photons, &mut rng)
+            })
+            .collect();
+        PhotonMap::new(photons)
+    }
+
+    /// Default radius of the first pass, for the map of pass 0
+    ///
+    /// Bounding boxes may be unbounded, so the scene is measured by where photons landed.
+    fn measure(map: &PhotonMap) -> f64 {
+        Some(0.01 * map.size()).filter(|&r| r > 0.0).unwrap_or(1.0)
+    }
+
+    /// Light the photons within `radius` of `rec` bring, and `rec` scatters back along `ray`
+    fn gather(map: &PhotonMap, radius: f64, ray: &Ray, rec: &HitRecord) -> Color {
+        let mut sum = Color::zero();
+        map.for_each_within(rec.position, radius, |photon| {
+            let incoming = -photon.direction;
+            let cos = rec.normal.dot(incoming).abs();
+            if cos > 0.0 {
+                let ray_out = Ray::new(rec.position, incoming, ray.time());
+                sum += photon.power * rec.material.scattering(ray, rec, &ray_out) / cos;
+            }
+        });
+        sum / (PI * radius * radius)
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn begin_pass(&self, scene: &Scene, pass: u64) {
+        let mut current = self.pass.write().unwrap();
+        // without alpha every pass gathers from the map of pass 0, resumed renders too
+        if current.is_some() && self.alpha.is_none() {
+            return;
+        }
+        let pass = if self.alpha.is_some() { pass } else { 0 };
+        let map = self.trace(scene, pass);
+        let base_radius = match (&*current, self.radius) {
+            (Some(previous), _) => previous.base_radius,
+            (None, Some(radius)) => radius,
+            (None, None) if pass == 0 => PhotonMapper::measure(&map),
+            (None, None) => PhotonMapper::measure(&self.trace(scene, 0)),
+        };
+        *current = Some(PhotonPass {
+            map,
+            radius: self.radius(base_radius, pass),
+            base_radius,
+        });
+    }
+
+    fn photon_radius(&self) -> Option<f64> {
+        let current = self.pass.read().unwrap();
+        current.as_ref().map(|pass| pass.base_radius)
+    }
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _splats: &mut Vec<Splat>,
+        rng: &mut Sampler,
+    ) -> Color {
+        let current = self.pass.read().unwrap();
+        let photons = current
+            .as_ref()
+            .expect("photons are traced before every pass");
+
+        let mut radiance = Color::zero();
+        let mut throughput = Color::ones();
+        let mut ray = *ray;
+        // density a medium scattered `ray` with, none for camera and specular rays
+        let mut scatter_pdf = None;
+
+        for bounce in 0..self.max_depth {
+            let Some(rec) = scene.world.hit(&ray, 0.00001, f64::INFINITY, rng) else {
+                radiance += throughput * scene.background;
+                break;
+            };
+            let mut emitted = rec.material.emitted(&rec);
+            if let Some(scatter_pdf) = scatter_pdf {
+                if emitted.luminance() > 0.0 {
+                    emitted *=
+                        power_heuristic(scatter_pdf, light_pdf(scene.lights, &ray, rec.t, rng));
+                }
+            }
+            radiance += throughput * emitted;
+
+            match rec.material.scatter_monte_carlo(&ray, &rec, rng) {
+                None => break,
+                Some(ScatterRecord::Specular {
+                    specular_ray,
+                    attenuation,
+                }) => {
+                    throughput = throughput * attenuation;
+                    ray = specular_ray;
+                    scatter_pdf = None;
+                }
+                Some(ScatterRecord::Scatter { pdf, attenuation }) => {
+                    if !rec.material.is_volumetric() {
+                        let direct = direct_light(&ray, &rec, &pdf, scene, rng);
+                        let indirect =
+                            PhotonMapper::gather(&photons.map, photons.radius, &ray, &rec);
+                        radiance += throughput * attenuation * (direct + indirect);
+                        break;
+                    }
+                    // media hold no photons, so paths go on through them
+                    let direct = sample_light(&ray, &rec, &pdf, scene.world, scene.lights, rng);
+                    radiance += throughput * attenuation * direct;
+
+                    let scattered = Ray::new(rec.position, pdf.generate(rng), ray.time());
+                    let pdf_value = pdf.value(scattered.direction());
+                    if pdf_value <= 0.0 {
+                        break;
+                    }
+                    throughput =
+                        throughput * attenuation * rec.material.scattering(&ray, &rec, &scattered)
+                            / pdf_value;
+                    ray = scattered;
+                    scatter_pdf = Some(pdf_value);
+                }
+            }
+
+            if bounce + 1 >= self.rr_min_bounces {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+                if rng.gen::<f64>() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+        radiance
+    }
+}
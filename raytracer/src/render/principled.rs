@@ -0,0 +1,232 @@
+use std::sync::Arc;
+
+use crate::geom::ray::Ray;
+use crate::hit::hittable::HitRecord;
+use crate::render::color::Color;
+use crate::render::mat::{Material, ScatterRecord};
+use crate::render::microfacet::{
+    fresnel_dielectric, sample_scatter, shading_directions, MicrofacetLobe, GGX,
+};
+use crate::render::pdf::PDF;
+use crate::render::sampler::Sampler;
+use crate::render::texture::Texture;
+use crate::utils::PI;
+
+/// Roughness of the clearcoat layer
+const CLEARCOAT_ROUGHNESS: f64 = 0.1;
+/// Index of refraction of the clearcoat layer
+const CLEARCOAT_IOR: f64 = 1.5;
+
+/// Scalar material parameter, constant or read from one channel of a texture
+#[derive(Clone)]
+pub enum Parameter {
+    Constant(f64),
+    Channel(Arc<dyn Texture>, usize),
+}
+
+impl Parameter {
+    pub fn value(&self, rec: &HitRecord) -> f64 {
+        match self {
+            Parameter::Constant(value) => value.clamp(0.0, 1.0),
+            Parameter::Channel(texture, channel) => {
+                texture.value(rec).get(*channel).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+impl From<f64> for Parameter {
+    fn from(value: f64) -> Parameter {
+        Parameter::Constant(value)
+    }
+}
+
+/// Parameters of a `Principled` material at a hit, and the weights of its lobes
+struct Lobes {
+    base_color: Color,
+    ggx: GGX,
+    /// Reflectance of the specular lobe at normal incidence
+    f0: Color,
+    eta: f64,
+    diffuse: f64,
+    sheen: f64,
+    specular: f64,
+    transmission: f64,
+    clearcoat: f64,
+}
+
+/// One material blending diffuse, sheen, metallic and dielectric specular, rough transmission
+/// and a clearcoat, after Burley's "Physically Based Shading at Disney"
+///
+/// Parameters range over `[0, 1]`. `specular` sets the reflectance of non-metals at normal
+/// incidence, 0.5 being the 4% of an index of refraction of 1.5, while transmission refracts
+/// through `ior`.
+#[derive(Clone)]
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Parameter,
+    roughness: Parameter,
+    specular: Parameter,
+    transmission: Parameter,
+    clearcoat: Parameter,
+    sheen: Parameter,
+    ior: f64,
+}
+
+impl Principled {
+    /// A rough plastic of `base_color`, until the other parameters are set
+    pub fn new(base_color: Arc<dyn Texture>) -> Principled {
+        Principled {
+            base_color,
+            metallic: Parameter::Constant(0.0),
+            roughness: Parameter::Constant(0.5),
+            specular: Parameter::Constant(0.5),
+            transmission: Parameter::Constant(0.0),
+            clearcoat: Parameter::Constant(0.0),
+            sheen: Parameter::Constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    pub fn with_metallic(self, metallic: impl Into<Parameter>) -> Principled {
+        Principled {
+            metallic: metallic.into(),
+            ..self
+        }
+    }
+
+    pub fn with_roughness(self, roughness: impl Into<Parameter>) -> Principled {
+        Principled {
+            roughness: roughness.into(),
+            ..self
+        }
+    }
+
+    pub fn with_specular(self, specular: impl Into<Parameter>) -> Principled {
+        Principled {
+            specular: specular.into(),
+            ..self
+        }
+    }
+
+    pub fn with_transmission(self, transmission: impl Into<Parameter>) -> Principled {
+        Principled {
+            transmission: transmission.into(),
+            ..self
+        }
+    }
+
+    pub fn with_clearcoat(self, clearcoat: impl Into<Parameter>) -> Principled {
+        Principled {
+            clearcoat: clearcoat.into(),
+            ..self
+        }
+    }
+
+    pub fn with_sheen(self, sheen: impl Into<Parameter>) -> Principled {
+        Principled {
+            sheen: sheen.into(),
+            ..self
+        }
+    }
+
+    pub fn with_ior(self, ior: f64) -> Principled {
+        Principled { ior, ..self }
+    }
+
+    fn lobes(&self, rec: &HitRecord) -> Lobes {
+        let base_color = self.base_color.value(rec);
+        let metallic = self.metallic.value(rec);
+        let transmission = self.transmission.value(rec);
+        let dielectric_f0 = 0.08 * self.specular.value(rec) * Color::ones();
+        Lobes {
+            base_color,
+            ggx: GGX::new(self.roughness.value(rec)),
+            f0: (1.0 - metallic) * dielectric_f0 + metallic * base_color,
+            eta: if rec.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+            diffuse: (1.0 - metallic) * (1.0 - transmission),
+            sheen: self.sheen.value(rec),
+            specular: 1.0 - (1.0 - metallic) * transmission,
+            transmission: (1.0 - metallic) * transmission,
+            clearcoat: self.clearcoat.value(rec),
+        }
+    }
+
+    /// Mixture of the lobes, picked roughly in proportion to the light they scatter, if any
+    /// lobe scatters at all
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord) -> Option<PDF<'static>> {
+        let lobes = self.lobes(rec);
+        let wo = -r_in.direction();
+        let frame = MicrofacetLobe::new(rec.normal, wo, lobes.ggx, None);
+        PDF::lobes_pdf(
+            frame,
+            lobes.diffuse,
+            [
+                (lobes.specular, lobes.ggx, None),
+                (lobes.transmission, lobes.ggx, Some(lobes.eta)),
+                (0.25 * lobes.clearcoat, GGX::new(CLEARCOAT_ROUGHNESS), None),
+            ],
+        )
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Sampler) -> Option<(Color, Ray)> {
+        sample_scatter(self, r_in, rec, rng)
+    }
+
+    fn scatter_monte_carlo(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _rng: &mut Sampler,
+    ) -> Option<ScatterRecord<'_>> {
+        if rec.normal.dot(r_in.direction()) >= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord::Scatter {
+            pdf: self.pdf(r_in, rec)?,
+            attenuation: Color::ones(),
+        })
+    }
+
+    fn scatter_pdf(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> f64 {
+        self.pdf(r_in, rec)
+            .map_or(0.0, |pdf| pdf.value(ray_out.direction()))
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, ray_out: &Ray) -> Color {
+        let (wo, wi) = shading_directions(r_in, rec, ray_out);
+        if wo.z <= 0.0 {
+            return Color::zero();
+        }
+        let lobes = self.lobes(rec);
+        let mut value = Color::zero();
+        if wi.z > 0.0 {
+            let (h, microfacets) = lobes.ggx.reflection(wo, wi);
+            let schlick = (1.0 - wo.dot(h)).clamp(0.0, 1.0).powi(5);
+            let sheen = lobes.sheen * schlick * Color::ones();
+            value += lobes.diffuse * (lobes.base_color / PI + sheen) * wi.z;
+            let fresnel = lobes.f0 + (Color::ones() - lobes.f0) * schlick;
+            value += lobes.specular * fresnel * microfacets;
+
+            let (h, coat) = GGX::new(CLEARCOAT_ROUGHNESS).reflection(wo, wi);
+            let fresnel = fresnel_dielectric(wo.dot(h), CLEARCOAT_IOR);
+            value += lobes.clearcoat * fresnel * coat * Color::ones();
+        }
+        if lobes.transmission > 0.0 {
+            // only light passing through the surface takes on its colour
+            let tint = if wi.z < 0.0 {
+                lobes.base_color
+            } else {
+                Color::ones()
+            };
+            value += lobes.transmission * lobes.ggx.dielectric(wo, wi, lobes.eta) * tint;
+        }
+        value
+    }
+}
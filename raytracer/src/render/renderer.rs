@@ -0,0 +1,397 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    geom::{
+        ray::Ray,
+        vec3::{Point3, Vec3},
+    },
+    hit::hittable::Hittable,
+    world::camera::Camera,
+};
+
+use super::{
+    color::Color,
+    film::{Film, PixelStats, Splat},
+    integrator::Integrator,
+    sampler::{pixel_sampler, Sampler},
+};
+
+/// A rectangle of pixels `[x0, x1) x [y0, y1)` in image coordinates, top row first
+#[derive(Copy, Clone, Debug)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+/// Split a `width` x `height` image into tiles of at most `size` x `size` pixels
+pub fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y0 in (0..height).step_by(size as usize) {
+        for x0 in (0..width).step_by(size as usize) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + size).min(width),
+                y1: (y0 + size).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+/// The film of a pass being rendered, with the splats of tiles that finished before the tiles
+/// ahead of them
+struct PassState<'a> {
+    film: &'a mut Film,
+    /// First tile whose splats are not in the film yet
+    next_tile: usize,
+    pending: BTreeMap<usize, Vec<Splat>>,
+}
+
+/// Samples drawn per pixel between two convergence checks
+const ADAPTIVE_BATCH: u64 = 8;
+
+/// Stop sampling a pixel once the relative standard error of its luminance drops below
+/// `threshold`, spending between `min_samples` and `max_samples` on it
+#[derive(Copy, Clone, Debug)]
+pub struct Adaptive {
+    pub min_samples: u64,
+    pub max_samples: u64,
+    pub threshold: f64,
+}
+
+/// What gets rendered: the world, the lights in it, and the camera looking at it through an
+/// image of `width` x `height` pixels
+pub struct Scene<'a> {
+    pub world: &'a dyn Hittable,
+    pub lights: &'a dyn Hittable,
+    pub background: Color,
+    pub camera: &'a Camera,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Scene<'_> {
+    /// A ray through a random point of pixel `(i, y)`
+    pub fn camera_ray(&self, i: u32, y: u32, rng: &mut Sampler) -> Ray {
+        // the camera's v axis points up, image rows go down
+        let j = self.height - y - 1;
+        let u = ((i as f64) + rng.gen::<f64>()) / ((self.width - 1) as f64);
+        let v = ((j as f64) + rng.gen::<f64>()) / ((self.height - 1) as f64);
+        self.camera.get_ray(u, v, rng)
+    }
+
+    /// The pixel that sees along `direction` from the point `lens` of the lens, if any
+    pub fn raster(&self, lens: Point3, direction: Vec3) -> Option<(u32, u32)> {
+        let (u, v) = self.camera.film_coordinates(lens, direction)?;
+        let i = (u * (self.width - 1) as f64).floor();
+        let j = (v * (self.height - 1) as f64).floor();
+        if i < 0.0 || j < 0.0 || i >= self.width as f64 || j >= self.height as f64 {
+            return None;
+        }
+        Some((i as u32, self.height - 1 - j as u32))
+    }
+
+    /// Density over solid angle of the directions of camera rays through random pixels
+    pub fn direction_pdf(&self, direction: Vec3) -> f64 {
+        // the pixels cover a little more than the unit square of film coordinates
+        let (w, h) = (self.width as f64, self.height as f64);
+        self.camera.direction_pdf(direction) * (w - 1.0) * (h - 1.0) / (w * h)
+    }
+}
+
+pub struct Renderer<'a> {
+    pub scene: Scene<'a>,
+    pub samples_per_pixel: u64,
+    pub integrator: &'a dyn Integrator,
+    pub tile_size: u32,
+    pub seed: u64,
+    pub adaptive: Option<Adaptive>,
+}
+
+impl Renderer<'_> {
+    /// Total samples a pixel may receive
+    fn target_samples(&self) -> u64 {
+        match self.adaptive {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.samples_per_pixel,
+        }
+    }
+
+    /// Keep adding passes of `pass_samples` samples per pixel to `film` until it holds the full
+    /// sample count, calling `on_pass` after each one
+    ///
+    /// A film that already holds samples, e.g. one loaded from a checkpoint, is continued where
+    /// it stopped.
+    pub fn render<F>(&self, film: &mut Film, pass_samples: u64, mut on_pass: F)
+    where
+        F: FnMut(&Film),
+    {
+        let target = self.target_samples();
+        let mut reached = film.max_samples();
+        if reached >= target {
+            return;
+        }
+        let passes = (target - reached).div_ceil(pass_samples);
+        let tiles = tiles(self.scene.width, self.scene.height, self.tile_size);
+        let bar = ProgressBar::new(passes * tiles.len() as u64);
+        bar.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_bar} {pos}/{len} tiles {msg} (eta {eta})",
+            )
+            .unwrap(),
+        );
+
+        for pass in 1..=passes {
+            self.integrator
+                .begin_pass(&self.scene, reached / pass_samples);
+            reached = (reached + pass_samples).min(target);
+            bar.set_message(format!("pass {}/{}", pass, passes));
+            self.render_pass(film, &tiles, reached, &bar);
+            on_pass(film);
+        }
+        bar.finish();
+    }
+
+    /// Bring every pixel of `film` up to `until` samples, in parallel over tiles
+    ///
+    /// Each tile's pixels are merged into the film as soon as it is done. Its splats wait for
+    /// those of the tiles before it, so that they are always summed in tile order and the image
+    /// does not depend on which thread finishes first.
+    fn render_pass(&self, film: &mut Film, tiles: &[Tile], until: u64, bar: &ProgressBar) {
+        let state = Mutex::new(PassState {
+            film,
+            next_tile: 0,
+            pending: BTreeMap::new(),
+        });
+        tiles.par_iter().enumerate().for_each(|(index, tile)| {
+            let previous: Vec<PixelStats> = {
+                let state = state.lock().unwrap();
+                let film = &state.film;
+                (tile.y0..tile.y1)
+                    .flat_map(|y| (tile.x0..tile.x1).map(move |x| (x, y)))
+                    .map(|(x, y)| *film.stats(x, y))
+                    .collect()
+            };
+            let (pixels, splats) = self.render_tile(&previous, tile, until);
+
+            let mut state = state.lock().unwrap();
+            let state = &mut *state;
+            let mut pixels = pixels.iter();
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    state.film.add(x, y, pixels.next().unwrap());
+                }
+            }
+            state.pending.insert(index, splats);
+            while let Some(splats) = state.pending.remove(&state.next_tile) {
+                for splat in &splats {
+                    state.film.splat(splat);
+                }
+                state.next_tile += 1;
+            }
+            bar.inc(1);
+        });
+    }
+
+    /// Trace `rays` camera rays to their first hit without shading them, returning how many hit
+    /// something and how long it took
+    ///
+    /// Measures intersection performance alone, e.g. to compare acceleration structures.
+    pub fn benchmark(&self, rays: u64) -> (u64, Duration) {
+        let width = self.scene.width as u64;
+        let pixels = width * self.scene.height as u64;
+        let start = Instant::now();
+        let hits = (0..rays)
+            .into_par_iter()
+            .filter(|&n| {
+                let pixel = n % pixels;
+                let (i, y) = ((pixel % width) as u32, (pixel / width) as u32);
+                let mut rng = pixel_sampler(self.seed, i, y, n / pixels);
+                let r = self.scene.camera_ray(i, y, &mut rng);
+                self.scene
+                    .world
+                    .hit(&r, 0.00001, f64::INFINITY, &mut rng)
+                    .is_some()
+            })
+            .count();
+        (hits as u64, start.elapsed())
+    }
+
+    fn sample(&self, i: u32, y: u32, sample: u64, splats: &mut Vec<Splat>) -> Color {
+        let mut rng = pixel_sampler(self.seed, i, y, sample);
+        let r = self.scene.camera_ray(i, y, &mut rng);
+        self.integrator.radiance(&r, &self.scene, splats, &mut rng)
+    }
+
+    /// Draw the samples `stats.samples..until` of one pixel, or fewer once an adaptive pixel
+    /// has converged, returning only the new samples
+    fn render_pixel(
+        &self,
+        i: u32,
+        y: u32,
+        stats: &PixelStats,
+        until: u64,
+        splats: &mut Vec<Splat>,
+    ) -> PixelStats {
+        let mut new = PixelStats::default();
+        let mut next = stats.samples;
+        match self.adaptive {
+            None => {
+                for sample in next..until {
+                    new.push(self.sample(i, y, sample, splats));
+                }
+            }
+            Some(adaptive) => {
+                let mut total = *stats;
+                while next < until {
+                    if total.samples >= adaptive.min_samples
+                        && total.relative_error() < adaptive.threshold
+                    {
+                        break;
+                    }
+                    let batch = ADAPTIVE_BATCH.min(until - next);
+                    let mut batch_stats = PixelStats::default();
+                    for sample in next..next + batch {
+                        batch_stats.push(self.sample(i, y, sample, splats));
+                    }
+                    total.merge(&batch_stats);
+                    new.merge(&batch_stats);
+                    next += batch;
+                }
+            }
+        }
+        new
+    }
+
+    /// Render one tile, whose pixels already hold `previous` in row-major order
+    fn render_tile(
+        &self,
+        previous: &[PixelStats],
+        tile: &Tile,
+        until: u64,
+    ) -> (Vec<PixelStats>, Vec<Splat>) {
+        let mut previous = previous.iter();
+        let mut pixels = Vec::with_capacity(previous.len());
+        let mut splats = Vec::new();
+        for y in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                let stats = previous.next().unwrap();
+                pixels.push(self.render_pixel(i, y, stats, until, &mut splats));
+            }
+        }
+        (pixels, splats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{cfg::Config, render::integrator::integrator_select, world::scene::scene_select};
+
+    /// Render a built-in scene with the command-line settings `args` on `threads` threads
+    fn render(args: &[&str], threads: usize) -> Film {
+        let cfg = Config::try_parse_from(["raytracer"].iter().chain(args)).unwrap();
+        let (world, lights, background, camera) = scene_select(&cfg).unwrap();
+        let integrator = integrator_select(&cfg);
+        let (width, height) = (cfg.image_width(), cfg.image_height());
+        let renderer = Renderer {
+            scene: Scene {
+                world: &*world,
+                lights: &*lights,
+                background,
+                camera: &camera,
+                width,
+                height,
+            },
+            samples_per_pixel: cfg.samples_per_pixel,
+            integrator: &*integrator,
+            tile_size: cfg.tile_size,
+            seed: cfg.seed(),
+            adaptive: None,
+        };
+        let mut film = Film::new(width, height);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| renderer.render(&mut film, cfg.pass_spp, |_| {}));
+        film
+    }
+
+    /// Average radiance over the image
+    fn mean(film: &Film) -> Color {
+        let mut sum = Color::zero();
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                sum += film.pixel(x, y);
+            }
+        }
+        sum / (film.width() * film.height()) as f64
+    }
+
+    #[test]
+    fn bdpt_matches_path_tracing_in_media() {
+        let args = |integrator| {
+            [
+                "--scene",
+                "cornell-smoke",
+                "--integrator",
+                integrator,
+                "-W",
+                "16",
+                "-s",
+                "256",
+                "--seed",
+                "1",
+            ]
+        };
+        let path = mean(&render(&args("path"), 1));
+        let bdpt = mean(&render(&args("bdpt"), 1));
+        for channel in 0..3 {
+            let (p, b) = (path.get(channel), bdpt.get(channel));
+            assert!(
+                (p - b).abs() < 0.02 * p,
+                "channel {}: path tracing gives {}, bdpt {}",
+                channel,
+                p,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn bdpt_does_not_depend_on_threads() {
+        let args = [
+            "--integrator",
+            "bdpt",
+            "-W",
+            "32",
+            "-s",
+            "4",
+            "--tile-size",
+            "4",
+            "--seed",
+            "1",
+        ];
+        let one = render(&args, 1);
+        let many = render(&args, 8);
+        for y in 0..one.height() {
+            for x in 0..one.width() {
+                assert_eq!(one.pixel(x, y), many.pixel(x, y), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+}
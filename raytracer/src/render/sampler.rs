@@ -0,0 +1,28 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg64Mcg;
+
+/// The random number generator threaded through camera, materials, PDFs and hittables
+pub type Sampler = Pcg64Mcg;
+
+fn splitmix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// An independent stream for one sample of one pixel
+///
+/// Only depends on its arguments, so the image does not depend on which thread renders what.
+pub fn pixel_sampler(seed: u64, x: u32, y: u32, sample: u64) -> Sampler {
+    let pixel = ((x as u64) << 32) | y as u64;
+    let hash = splitmix64(splitmix64(splitmix64(seed) ^ pixel) ^ sample);
+    Sampler::seed_from_u64(hash)
+}
+
+/// An independent stream for one batch of photons of one pass
+pub fn photon_sampler(seed: u64, pass: u64, batch: u64) -> Sampler {
+    // complementing the seed keeps these streams apart from the pixels'
+    let hash = splitmix64(splitmix64(splitmix64(!seed) ^ pass) ^ batch);
+    Sampler::seed_from_u64(hash)
+}
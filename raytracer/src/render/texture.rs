@@ -1,9 +1,28 @@
-use crate::geom::vec3::Vec3;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{geom::vec3::Vec3, hit::hittable::HitRecord};
 
 use super::{color::Color, perlin::Perlin};
 
-pub trait Texture: Sync {
+pub trait Texture: Send + Sync {
     fn texture_map(&self, u: f64, v: f64, p: &Vec3) -> Color;
+
+    /// Colour at a hit, for textures that need more of it than its coordinates
+    fn value(&self, rec: &HitRecord) -> Color {
+        self.texture_map(rec.u, rec.v, &rec.position)
+    }
+}
+
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn texture_map(&self, u: f64, v: f64, p: &Vec3) -> Color {
+        (**self).texture_map(u, v, p)
+    }
+
+    fn value(&self, rec: &HitRecord) -> Color {
+        (**self).value(rec)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -44,6 +63,16 @@ impl<T: Texture, U: Texture> Texture for CheckerTexture<T, U> {
             self.even.texture_map(u, v, p)
         }
     }
+
+    fn value(&self, rec: &HitRecord) -> Color {
+        let p = rec.position;
+        let sines = f64::sin(10.0 * p.x) * f64::sin(10.0 * p.y) * f64::sin(10.0 * p.z);
+        if sines < 0.0 {
+            self.odd.value(rec)
+        } else {
+            self.even.value(rec)
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -88,6 +117,20 @@ impl Texture for ImageTexture {
     }
 }
 
+/// Colour interpolated from the vertices of the mesh that was hit, white elsewhere
+#[derive(Copy, Clone)]
+pub struct VertexColorTexture;
+
+impl Texture for VertexColorTexture {
+    fn texture_map(&self, _u: f64, _v: f64, _p: &Vec3) -> Color {
+        Color::ones()
+    }
+
+    fn value(&self, rec: &HitRecord) -> Color {
+        rec.color.unwrap_or(Color::ones())
+    }
+}
+
 #[derive(Clone)]
 pub struct NoiseTexture {
     noise: Perlin,
@@ -95,9 +138,9 @@ pub struct NoiseTexture {
 }
 
 impl NoiseTexture {
-    pub fn new(scale: f64) -> NoiseTexture {
+    pub fn new<R: Rng + ?Sized>(scale: f64, rng: &mut R) -> NoiseTexture {
         NoiseTexture {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale,
         }
     }
@@ -0,0 +1,95 @@
+use clap::ValueEnum;
+
+use super::color::Color;
+
+/// Curve compressing linear radiance into `[0, 1]`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Operator {
+    /// Hard clip at 1
+    Clamp,
+    /// x / (1 + x) per channel
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// Hable's Uncharted 2 curve
+    Filmic,
+}
+
+/// Opto-electronic transfer function encoding the display value
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Transfer {
+    /// Square root, the historical output of this renderer
+    Gamma2,
+    /// The piecewise sRGB curve
+    Srgb,
+}
+
+/// Display transform applied to the linear film before it is quantised to 8 bits
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMap {
+    /// Exposure adjustment in stops
+    pub exposure: f64,
+    pub operator: Operator,
+    pub transfer: Transfer,
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+impl Operator {
+    fn map(self, x: f64) -> f64 {
+        match self {
+            Operator::Clamp => x,
+            Operator::Reinhard => x / (1.0 + x),
+            Operator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (x * (a * x + b)) / (x * (c * x + d) + e)
+            }
+            Operator::Filmic => {
+                let exposure_bias = 2.0;
+                let white = 11.2;
+                hable(exposure_bias * x) / hable(white)
+            }
+        }
+    }
+}
+
+impl Transfer {
+    fn encode(self, x: f64) -> f64 {
+        match self {
+            Transfer::Gamma2 => x.sqrt(),
+            Transfer::Srgb => {
+                if x <= 0.003_130_8 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+impl ToneMap {
+    /// Map linear radiance to encoded display values in `[0, 1]`
+    pub fn apply(&self, color: Color) -> Color {
+        let scale = self.exposure.exp2();
+        let channel = |x: f64| {
+            // NaN and negative radiance carry no meaning on a display
+            let x = if x.is_nan() {
+                0.0
+            } else {
+                (x * scale).max(0.0)
+            };
+            self.transfer.encode(self.operator.map(x).clamp(0.0, 1.0))
+        };
+        Color::new(channel(color.x), channel(color.y), channel(color.z))
+    }
+
+    pub fn quantise(&self, color: Color) -> [u8; 3] {
+        let c = self.apply(color);
+        let to_u8 = |x: f64| (256.0 * x.clamp(0.0, 0.999)) as u8;
+        [to_u8(c.x), to_u8(c.y), to_u8(c.z)]
+    }
+}
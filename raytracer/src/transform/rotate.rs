@@ -5,6 +5,7 @@ use crate::{
         aabb::AABB,
         hittable::{HitRecord, Hittable},
     },
+    render::sampler::Sampler,
 };
 
 pub enum Axis {
@@ -87,8 +88,26 @@ impl<H: Hittable> Rotate<H> {
     }
 }
 
+impl<H: Hittable> Rotate<H> {
+    fn to_object(&self, mut v: Vec3) -> Vec3 {
+        let (_, a_axis, b_axis) = get_axis_index(&self.axis);
+        let (a, b) = (v.get(a_axis), v.get(b_axis));
+        v.set(a_axis, self.cos_theta * a - self.sin_theta * b);
+        v.set(b_axis, self.sin_theta * a + self.cos_theta * b);
+        v
+    }
+
+    fn to_world(&self, mut v: Vec3) -> Vec3 {
+        let (_, a_axis, b_axis) = get_axis_index(&self.axis);
+        let (a, b) = (v.get(a_axis), v.get(b_axis));
+        v.set(a_axis, self.cos_theta * a + self.sin_theta * b);
+        v.set(b_axis, -self.sin_theta * a + self.cos_theta * b);
+        v
+    }
+}
+
 impl<H: Hittable> Hittable for Rotate<H> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
         let (_, a_axis, b_axis) = get_axis_index(&self.axis);
         let mut origin = r.origin();
         let mut direction = r.direction();
@@ -114,7 +133,7 @@ impl<H: Hittable> Hittable for Rotate<H> {
         let rotated_ray = Ray::new(origin, direction, r.time());
 
         self.hittable
-            .hit(&rotated_ray, t_min, t_max)
+            .hit(&rotated_ray, t_min, t_max, rng)
             .map(|mut hit| {
                 let mut position = hit.position;
                 let mut normal = hit.normal;
@@ -150,4 +169,39 @@ impl<H: Hittable> Hittable for Rotate<H> {
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
         self.aabb
     }
+
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.hittable
+            .pdf_value(self.to_object(o), self.to_object(v))
+    }
+
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        self.to_world(self.hittable.random(self.to_object(o), rng))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        let rotated_ray = Ray::new(
+            self.to_object(r.origin()),
+            self.to_object(r.direction()),
+            r.time(),
+        );
+        self.hittable.transmittance(&rotated_ray, t_min, t_max, rng)
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        self.hittable.sample_surface(rng).map(|(mut rec, pdf)| {
+            rec.position = self.to_world(rec.position);
+            rec.normal = self.to_world(rec.normal);
+            (rec, pdf)
+        })
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        let rotated_ray = Ray::new(
+            self.to_object(r.origin()),
+            self.to_object(r.direction()),
+            r.time(),
+        );
+        self.hittable.surface_pdf(&rotated_ray)
+    }
 }
@@ -2,6 +2,7 @@
 use crate::geom::vec3::Vec3;
 use crate::hit::aabb::AABB;
 use crate::hit::hittable::{HitRecord, Hittable};
+use crate::render::sampler::Sampler;
 
 pub struct Translate<H: Hittable> {
     hittable: H,
@@ -15,10 +16,10 @@ impl<H: Hittable> Translate<H> {
 }
 
 impl<H: Hittable> Hittable for Translate<H> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
         let translated_ray = Ray::new(r.origin() - self.offset, r.direction(), r.time());
         self.hittable
-            .hit(&translated_ray, t_min, t_max)
+            .hit(&translated_ray, t_min, t_max, rng)
             .map(|mut hit| {
                 hit.position += self.offset;
                 hit
@@ -32,4 +33,30 @@ impl<H: Hittable> Hittable for Translate<H> {
             aabb
         })
     }
+
+    fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+        self.hittable.pdf_value(o - self.offset, v)
+    }
+
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        self.hittable.random(o - self.offset, rng)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        let translated_ray = Ray::new(r.origin() - self.offset, r.direction(), r.time());
+        self.hittable
+            .transmittance(&translated_ray, t_min, t_max, rng)
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        self.hittable.sample_surface(rng).map(|(mut rec, pdf)| {
+            rec.position += self.offset;
+            (rec, pdf)
+        })
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        let translated_ray = Ray::new(r.origin() - self.offset, r.direction(), r.time());
+        self.hittable.surface_pdf(&translated_ray)
+    }
 }
@@ -1,5 +1,6 @@
 use crate::geom::ray::Ray;
 use crate::geom::vec3::{Point3, Vec3};
+use crate::render::sampler::Sampler;
 use rand::Rng;
 use std::f64;
 
@@ -16,6 +17,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -52,17 +54,58 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
-        let offset = self.cu * rd.x + self.cv * rd.y;
+    /// Direction the camera looks in
+    pub fn forward(&self) -> Vec3 {
+        Vec3::cross(self.cv, self.cu)
+    }
+
+    /// A point on the lens, distributed like the origins of `get_ray`
+    pub fn sample_lens(&self, rng: &mut Sampler) -> Point3 {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk(rng);
+        self.origin + self.cu * rd.x + self.cv * rd.y
+    }
+
+    /// A moment while the shutter is open, distributed like the times of `get_ray`
+    pub fn sample_time(&self, rng: &mut Sampler) -> f64 {
+        self.time0 + rng.gen::<f64>() * (self.time1 - self.time0)
+    }
+
+    /// Film coordinates `(u, v)`, as `get_ray` takes them, of the point in focus seen from `lens`
+    /// in `direction`, if it is in front of the camera
+    pub fn film_coordinates(&self, lens: Point3, direction: Vec3) -> Option<(f64, f64)> {
+        let forward = self.forward();
+        let cos = direction.dot(forward);
+        if cos <= 0.0 {
+            return None;
+        }
+        let t = (self.lower_left_corner - lens).dot(forward) / cos;
+        let on_film = lens + t * direction - self.lower_left_corner;
+        Some((
+            on_film.dot(self.horizontal) / self.horizontal.squared_length(),
+            on_film.dot(self.vertical) / self.vertical.squared_length(),
+        ))
+    }
+
+    /// Density over solid angle of the directions of `get_ray` for film coordinates uniform over
+    /// `[0, 1] x [0, 1]`
+    pub fn direction_pdf(&self, direction: Vec3) -> f64 {
+        let forward = self.forward();
+        let cos = direction.unit().dot(forward);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        let focus_dist = (self.lower_left_corner - self.origin).dot(forward);
+        let area = self.horizontal.length() * self.vertical.length();
+        focus_dist * focus_dist / (area * cos.powi(3))
+    }
 
-        let time = self.time0 + rand::thread_rng().gen::<f64>() * (self.time1 - self.time0);
+    pub fn get_ray(&self, u: f64, v: f64, rng: &mut Sampler) -> Ray {
+        let lens = self.sample_lens(rng);
 
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical
-                - (self.origin + offset),
-            time,
+            lens,
+            self.lower_left_corner + u * self.horizontal + v * self.vertical - lens,
+            self.sample_time(rng),
         )
     }
 }
@@ -0,0 +1,522 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use gltf::{camera::Projection, image::Format, khr_lights_punctual::Kind};
+
+use crate::{
+    cfg::Config,
+    geom::{
+        mesh::{Mesh, MeshData, DEFAULT_CREASE_ANGLE},
+        sphere::Sphere,
+        vec3::{Point3, Vec3},
+    },
+    hit::{aabb::surrounding_box, hittable::Hittable},
+    render::{
+        color::Color,
+        mat::{DiffuseLight, Material},
+        principled::{Parameter, Principled},
+        texture::{ConstantTexture, ImageTexture, Texture},
+    },
+    world::{camera::Camera, hittablelist::HittableList, scene::build_bvh},
+};
+
+/// Column-major affine transform, as glTF stores it
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Punctual lights become small emitting spheres, this big relative to the scene
+const LIGHT_RADIUS: f64 = 0.005;
+/// Directional lights become spheres this many scene radii away
+const SUN_DISTANCE: f64 = 100.0;
+/// Angular radius of the sphere standing in for a directional light
+const SUN_ANGLE: f64 = 0.5;
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (col, b_col) in m.iter_mut().zip(b) {
+        for (row, value) in col.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b_col[k]).sum();
+        }
+    }
+    m
+}
+
+fn transform_point(m: &Matrix, p: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * p.x + m[1][0] * p.y + m[2][0] * p.z + m[3][0],
+        m[0][1] * p.x + m[1][1] * p.y + m[2][1] * p.z + m[3][1],
+        m[0][2] * p.x + m[1][2] * p.y + m[2][2] * p.z + m[3][2],
+    )
+}
+
+/// Columns of the linear part of `m`
+fn axes(m: &Matrix) -> [Vec3; 3] {
+    [0, 1, 2].map(|c| Vec3::new(m[c][0], m[c][1], m[c][2]))
+}
+
+/// Map normals through `m`: the cofactor matrix is the inverse transpose up to the determinant,
+/// whose sign keeps them facing out of mirrored geometry
+fn transform_normal(m: &Matrix, n: Vec3) -> Vec3 {
+    let [x, y, z] = axes(m);
+    let (cx, cy, cz) = (Vec3::cross(y, z), Vec3::cross(z, x), Vec3::cross(x, y));
+    let det = x.dot(cx);
+    let n = n.x * cx + n.y * cy + n.z * cz;
+    if det < 0.0 {
+        -n
+    } else {
+        n
+    }
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+/// Light given off by `material`, if any
+fn emission(material: &gltf::Material) -> Option<Color> {
+    let emissive =
+        vec3(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0) as f64;
+    (emissive.x.max(emissive.y).max(emissive.z) > 0.0).then_some(emissive)
+}
+
+/// Make the vertex normals of `data` unit length, giving those that transformed to zero the
+/// normal of a face around them so every vertex keeps one
+fn repair_normals(data: &mut MeshData) {
+    for face in &data.faces {
+        let [a, b, c] = face.map(|i| data.positions[i as usize]);
+        let face_normal = Vec3::cross(b - a, c - a);
+        for &i in face {
+            let normal = &mut data.normals[i as usize];
+            if normal.squared_length() == 0.0 && face_normal.squared_length() > 0.0 {
+                *normal = face_normal;
+            }
+        }
+    }
+    for normal in &mut data.normals {
+        if normal.squared_length() > 0.0 {
+            *normal = normal.unit();
+        }
+    }
+}
+
+/// Convert a decoded glTF image to 8-bit RGB, multiplying every channel by `factor`
+fn image_texture(image: &gltf::image::Data, factor: Color) -> Result<ImageTexture, String> {
+    let channels = match image.format {
+        Format::R8 => 1,
+        Format::R8G8 => 2,
+        Format::R8G8B8 => 3,
+        Format::R8G8B8A8 => 4,
+        format => return Err(format!("unsupported image format {:?}", format)),
+    };
+    let data = image
+        .pixels
+        .chunks_exact(channels)
+        .flat_map(|px| {
+            let rgb = match channels {
+                1 | 2 => [px[0]; 3],
+                _ => [px[0], px[1], px[2]],
+            };
+            [
+                (rgb[0] as f64 * factor.x).min(255.0) as u8,
+                (rgb[1] as f64 * factor.y).min(255.0) as u8,
+                (rgb[2] as f64 * factor.z).min(255.0) as u8,
+            ]
+        })
+        .collect();
+    Ok(ImageTexture::new(data, image.width, image.height))
+}
+
+struct GltfLoader<'a> {
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    materials: HashMap<Option<usize>, Arc<dyn Material>>,
+    normal_maps: HashMap<usize, Arc<dyn Texture>>,
+    meshes: Vec<Box<dyn Hittable>>,
+    cameras: Vec<(Matrix, gltf::Camera<'a>)>,
+    lights: Vec<(Matrix, gltf::khr_lights_punctual::Light<'a>)>,
+    /// Meshes with an emissive material, also sampled as lights
+    emitters: Vec<Arc<Mesh>>,
+    cfg: &'a Config,
+}
+
+impl<'a> GltfLoader<'a> {
+    /// Map a metallic-roughness material onto a principled one
+    ///
+    /// Emission makes a light instead. Textures are scaled by their factors.
+    fn material(&mut self, material: gltf::Material) -> Result<Arc<dyn Material>, String> {
+        if let Some(m) = self.materials.get(&material.index()) {
+            return Ok(m.clone());
+        }
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base = vec3([r, g, b]);
+
+        let m: Arc<dyn Material> = if let Some(emission) = emission(&material) {
+            Arc::new(DiffuseLight::new(ConstantTexture::new(emission)))
+        } else {
+            let base_color: Arc<dyn Texture> = match pbr.base_color_texture() {
+                Some(info) => Arc::new(self.texture(info.texture(), base)?),
+                None => Arc::new(ConstantTexture::new(base)),
+            };
+            let metallic = pbr.metallic_factor() as f64;
+            let roughness = pbr.roughness_factor() as f64;
+            // roughness is in the green channel of the texture, metalness in the blue
+            let (metallic, roughness) = match pbr.metallic_roughness_texture() {
+                Some(info) => {
+                    let texture: Arc<dyn Texture> = Arc::new(
+                        self.texture(info.texture(), Color::new(1.0, roughness, metallic))?,
+                    );
+                    (
+                        Parameter::Channel(texture.clone(), 2),
+                        Parameter::Channel(texture, 1),
+                    )
+                }
+                None => (
+                    Parameter::Constant(metallic),
+                    Parameter::Constant(roughness),
+                ),
+            };
+            let transmission = match material.transmission() {
+                Some(t) => {
+                    let factor = t.transmission_factor() as f64;
+                    match t.transmission_texture() {
+                        Some(info) => Parameter::Channel(
+                            Arc::new(self.texture(info.texture(), Color::ones() * factor)?),
+                            0,
+                        ),
+                        None => Parameter::Constant(factor),
+                    }
+                }
+                None => Parameter::Constant(0.0),
+            };
+            Arc::new(
+                Principled::new(base_color)
+                    .with_metallic(metallic)
+                    .with_roughness(roughness)
+                    .with_transmission(transmission)
+                    .with_ior(material.ior().unwrap_or(1.5) as f64),
+            )
+        };
+        self.materials.insert(material.index(), m.clone());
+        Ok(m)
+    }
+
+    /// Image of `texture` with its channels multiplied by `factor`
+    fn texture(&self, texture: gltf::Texture, factor: Color) -> Result<ImageTexture, String> {
+        image_texture(&self.images[texture.source().index()], factor)
+    }
+
+    fn normal_map(&mut self, image: usize) -> Result<Arc<dyn Texture>, String> {
+        if let Some(map) = self.normal_maps.get(&image) {
+            return Ok(map.clone());
+        }
+        let map: Arc<dyn Texture> = Arc::new(image_texture(&self.images[image], Color::ones())?);
+        self.normal_maps.insert(image, map.clone());
+        Ok(map)
+    }
+
+    fn mesh(&mut self, mesh: gltf::Mesh, transform: &Matrix) -> Result<(), String> {
+        for (p, primitive) in mesh.primitives().enumerate() {
+            let name = format!("{}#{}", mesh.name().unwrap_or("mesh"), p);
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                println!(
+                    "Skipping {}: {:?} are not supported",
+                    name,
+                    primitive.mode()
+                );
+                continue;
+            }
+            let buffers = &self.buffers;
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<Point3> = reader
+                .read_positions()
+                .ok_or_else(|| format!("{} has no positions", name))?
+                .map(|p| transform_point(transform, vec3(p)))
+                .collect();
+            let normals = reader.read_normals().map_or_else(Vec::new, |normals| {
+                normals
+                    .map(|n| transform_normal(transform, vec3(n)))
+                    .collect()
+            });
+            let material = primitive.material();
+            let normal_texture = material.normal_texture();
+            let uv_set = material
+                .pbr_metallic_roughness()
+                .base_color_texture()
+                .map_or(0, |info| info.tex_coord());
+            // glTF puts the texture origin top left, our textures bottom left
+            let uvs = reader.read_tex_coords(uv_set).map_or_else(Vec::new, |uvs| {
+                uvs.into_f32()
+                    .map(|[u, v]| [u as f64, 1.0 - v as f64])
+                    .collect()
+            });
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let n = positions.len();
+            if let Some(face) = indices
+                .chunks_exact(3)
+                .find(|face| face.iter().any(|&i| i as usize >= n))
+            {
+                return Err(format!(
+                    "{}: face {:?} indexes past the vertices",
+                    name, face
+                ));
+            }
+            println!("Loading {} with {} triangles", name, indices.len() / 3);
+
+            let mut data = MeshData {
+                normals: if normals.len() == positions.len() {
+                    normals
+                } else {
+                    Vec::new()
+                },
+                uvs: if uvs.len() == positions.len() {
+                    uvs
+                } else {
+                    Vec::new()
+                },
+                positions,
+                colors: Vec::new(),
+                faces: indices
+                    .chunks_exact(3)
+                    .map(|f| [f[0], f[1], f[2]])
+                    .collect(),
+            };
+            if data.normals.is_empty() {
+                data.remove_degenerate_faces();
+                data.generate_normals(DEFAULT_CREASE_ANGLE);
+            } else {
+                repair_normals(&mut data);
+            }
+            let emissive = emission(&material).is_some();
+            let material = self.material(material)?;
+            let mut mesh = match Mesh::new(&name, data, material, self.cfg.bvh_leaf_size) {
+                Ok(mesh) => mesh,
+                Err(err) => {
+                    println!("Skipping {}", err);
+                    continue;
+                }
+            };
+            if let Some(normal_texture) = normal_texture {
+                let map = self.normal_map(normal_texture.texture().source().index())?;
+                mesh = mesh.with_normal_map(map, normal_texture.scale() as f64);
+            }
+            if self.cfg.bvh_stats {
+                println!("BVH {}: {}", mesh.name(), mesh.stats());
+            }
+            if emissive {
+                let mesh = Arc::new(mesh);
+                self.emitters.push(mesh.clone());
+                self.meshes.push(Box::new(mesh));
+            } else {
+                self.meshes.push(Box::new(mesh));
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: gltf::Node<'a>, parent: &Matrix) -> Result<(), String> {
+        let local = node.transform().matrix().map(|col| col.map(|x| x as f64));
+        let transform = multiply(parent, &local);
+        if let Some(mesh) = node.mesh() {
+            self.mesh(mesh, &transform)?;
+        }
+        if let Some(camera) = node.camera() {
+            self.cameras.push((transform, camera));
+        }
+        if let Some(light) = node.light() {
+            self.lights.push((transform, light));
+        }
+        for child in node.children() {
+            self.node(child, &transform)?;
+        }
+        Ok(())
+    }
+}
+
+/// Load the default scene of a glTF 2.0 (`.gltf` or `.glb`) file
+///
+/// Node transforms are baked into the meshes, each primitive becoming a `Mesh` with its own
+/// material. The first camera is used, or one looking down -z at the whole scene if there is
+/// none. Punctual lights become small emitting spheres; spot lights lose their cone, and
+/// directional lights become a distant sun, and emissive primitives are sampled as lights.
+/// Without any lights the scene is lit by a sky.
+#[allow(clippy::type_complexity)]
+pub fn load_gltf<P: AsRef<Path>>(
+    path: P,
+    cfg: &Config,
+) -> Result<(Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera), String> {
+    let path = path.as_ref();
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|err| format!("Failed to load {}: {}", path.display(), err))?;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("{}: no scene", path.display()))?;
+
+    let mut loader = GltfLoader {
+        buffers,
+        images,
+        materials: HashMap::new(),
+        normal_maps: HashMap::new(),
+        meshes: Vec::new(),
+        cameras: Vec::new(),
+        lights: Vec::new(),
+        emitters: Vec::new(),
+        cfg,
+    };
+    for node in scene.nodes() {
+        loader
+            .node(node, &IDENTITY)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    if loader.meshes.is_empty() {
+        return Err(format!("{}: scene has no meshes", path.display()));
+    }
+
+    let bounds = loader
+        .meshes
+        .iter()
+        .filter_map(|mesh| mesh.bounding_box(0.0, 1.0))
+        .reduce(|a, b| surrounding_box(&a, &b))
+        .unwrap();
+    let center = bounds.centroid();
+    let radius = 0.5 * (bounds.max - bounds.min).length();
+
+    let aspect_ratio = cfg.aspect_ratio();
+    let camera = match loader.cameras.first() {
+        Some((transform, camera)) => {
+            let vfov = match camera.projection() {
+                Projection::Perspective(perspective) => perspective.yfov() as f64,
+                Projection::Orthographic(_) => {
+                    return Err(format!(
+                        "{}: orthographic cameras are not supported",
+                        path.display()
+                    ))
+                }
+            };
+            let lookfrom = transform_point(transform, Vec3::zero());
+            let [_, up, back] = axes(transform);
+            Camera::new(
+                lookfrom,
+                lookfrom - back,
+                up,
+                vfov.to_degrees(),
+                aspect_ratio,
+                0.0,
+                1.0,
+                0.0,
+                1.0,
+            )
+        }
+        None => {
+            let vfov: f64 = 40.0;
+            let distance = radius / (0.5 * vfov.to_radians()).sin();
+            Camera::new(
+                center + Vec3::new(0.0, 0.0, distance),
+                center,
+                Vec3::new(0.0, 1.0, 0.0),
+                vfov,
+                aspect_ratio,
+                0.0,
+                1.0,
+                0.0,
+                1.0,
+            )
+        }
+    };
+
+    let mut world = std::mem::take(&mut loader.meshes);
+    let mut lights = HittableList::default();
+    for (transform, light) in &loader.lights {
+        let emit = vec3(light.color()) * light.intensity() as f64;
+        let (position, r, radiance) = match light.kind() {
+            Kind::Point | Kind::Spot { .. } => {
+                // a sphere of radiance L has the intensity L * pi * r^2 of a point light
+                let r = LIGHT_RADIUS * radius;
+                let position = transform_point(transform, Vec3::zero());
+                (position, r, emit / (std::f64::consts::PI * r * r))
+            }
+            Kind::Directional => {
+                // and seen under the angle a gives the illuminance L * pi * sin^2(a)
+                let [_, _, back] = axes(transform);
+                let distance = SUN_DISTANCE * radius;
+                let position = center + distance * back.unit();
+                let sin = SUN_ANGLE.to_radians().sin();
+                let radiance = emit / (std::f64::consts::PI * sin * sin);
+                (position, distance * sin, radiance)
+            }
+        };
+        let material: Arc<dyn Material> =
+            Arc::new(DiffuseLight::new(ConstantTexture::new(radiance)));
+        world.push(Box::new(Sphere::new(position, r, material.clone())));
+        lights.push(Sphere::new(position, r, material));
+    }
+
+    for mesh in &loader.emitters {
+        lights.push(mesh.clone());
+    }
+
+    let background = if loader.lights.is_empty() && loader.emitters.is_empty() {
+        Color::new(0.7, 0.8, 1.0)
+    } else {
+        Color::zero()
+    };
+
+    let world = build_bvh("world", world, 0.0, 1.0, cfg);
+    Ok((Box::new(world), Box::new(lights), background, camera))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::{geom::ray::Ray, render::sampler::pixel_sampler};
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn config() -> Config {
+        Config::try_parse_from(["raytracer"]).unwrap()
+    }
+
+    #[test]
+    fn rejects_indices_past_the_vertices() {
+        let Err(err) = load_gltf(fixture("bad_index.gltf"), &config()) else {
+            panic!("bad_index.gltf was loaded");
+        };
+        assert!(err.ends_with("indexes past the vertices"), "{}", err);
+    }
+
+    #[test]
+    fn samples_emissive_primitives() {
+        let (world, lights, background, _) =
+            load_gltf(fixture("emissive_triangle.gltf"), &config()).unwrap();
+        assert_eq!(background, Color::zero());
+
+        let mut rng = pixel_sampler(0, 0, 0, 0);
+        let (rec, pdf) = lights.sample_surface(&mut rng).unwrap();
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(pdf, 2.0);
+        let o = Point3::new(0.25, 0.25, 1.0);
+        assert!((lights.pdf_value(o, Vec3::new(0.0, 0.0, -1.0)) - 2.0).abs() < 1e-9);
+
+        // the vertex whose normal is zero takes the face's instead
+        let r = Ray::new(Point3::new(0.05, 0.9, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = world.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
     geom::{ray::Ray, vec3::Vec3},
     hit::aabb::{surrounding_box, AABB},
     hit::hittable::{HitRecord, Hittable},
+    render::sampler::Sampler,
 };
 
 #[derive(Default)]
@@ -18,12 +19,12 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> Option<HitRecord<'_>> {
         let mut temp_rec = None;
         let mut cloest_so_far = t_max;
 
         for object in &self.list {
-            if let Some(rec) = object.hit(r, t_min, cloest_so_far) {
+            if let Some(rec) = object.hit(r, t_min, cloest_so_far, rng) {
                 cloest_so_far = rec.t;
                 temp_rec = Some(rec);
             }
@@ -47,7 +48,27 @@ impl Hittable for HittableList {
         self.list.iter().map(|h| h.pdf_value(o, v)).sum::<f64>() / self.list.len() as f64
     }
 
-    fn random(&self, o: Vec3) -> Vec3 {
-        self.list.choose(&mut rand::thread_rng()).unwrap().random(o)
+    fn random(&self, o: Vec3, rng: &mut Sampler) -> Vec3 {
+        self.list.choose(rng).unwrap().random(o, rng)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Sampler) -> f64 {
+        let mut transmittance = 1.0;
+        for object in &self.list {
+            transmittance *= object.transmittance(r, t_min, t_max, rng);
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
+
+    fn sample_surface(&self, rng: &mut Sampler) -> Option<(HitRecord<'_>, f64)> {
+        let (rec, pdf) = self.list.choose(rng)?.sample_surface(rng)?;
+        Some((rec, pdf / self.list.len() as f64))
+    }
+
+    fn surface_pdf(&self, r: &Ray) -> f64 {
+        self.list.iter().map(|h| h.surface_pdf(r)).sum::<f64>() / self.list.len() as f64
     }
 }
@@ -0,0 +1,751 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    cfg::Config,
+    geom::{
+        cube::Cube,
+        medium::{ConstantMedium, HeterogeneousMedium, NoiseDensity, VoxelGrid},
+        mesh::{Mesh, DEFAULT_CREASE_ANGLE},
+        quad::{Plane, Quad},
+        sphere::{MovingSphere, Sphere},
+        triangle::Triangle,
+        vec3::Vec3,
+    },
+    hit::hittable::{FlipNormal, Hittable},
+    render::{
+        color::Color,
+        mat::{Dielectric, DiffuseLight, HenyeyGreenstein, Isotropic, Lambertian, Material, Metal},
+        microfacet::{Conductor, RoughDielectric},
+        principled::{Parameter, Principled},
+        texture::{CheckerTexture, ConstantTexture, ImageTexture, NoiseTexture, Texture},
+    },
+    transform::{
+        rotate::{Axis, Rotate},
+        translate::Translate,
+    },
+    world::{camera::Camera, hittablelist::HittableList, scene::build_bvh},
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    background: [f64; 3],
+    camera: CameraDesc,
+    #[serde(default)]
+    textures: BTreeMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDesc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    focus_dist: Option<f64>,
+    #[serde(default)]
+    time0: f64,
+    #[serde(default = "default_time1")]
+    time1: f64,
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_time1() -> f64 {
+    1.0
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_crease_angle() -> f64 {
+    DEFAULT_CREASE_ANGLE
+}
+
+/// Either the name of a texture or an inline constant color
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+    Color([f64; 3]),
+    Named(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Constant { color: [f64; 3] },
+    Checker { odd: TextureRef, even: TextureRef },
+    Image { path: PathBuf },
+    Noise { scale: f64 },
+}
+
+/// Either a constant or one channel of a named texture, red by default
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParameterRef {
+    Value(f64),
+    Texture {
+        texture: String,
+        #[serde(default)]
+        channel: usize,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: [f64; 3],
+        fuzz: f64,
+    },
+    Dielectric {
+        ir: f64,
+    },
+    /// GGX metal with complex index of refraction `eta + ik` per channel
+    Conductor {
+        eta: [f64; 3],
+        k: [f64; 3],
+        roughness: f64,
+    },
+    RoughDielectric {
+        ir: f64,
+        roughness: f64,
+    },
+    /// Parameters left out keep the defaults of `Principled::new`
+    Principled {
+        base_color: TextureRef,
+        metallic: Option<ParameterRef>,
+        roughness: Option<ParameterRef>,
+        specular: Option<ParameterRef>,
+        transmission: Option<ParameterRef>,
+        clearcoat: Option<ParameterRef>,
+        sheen: Option<ParameterRef>,
+        ior: Option<f64>,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum PlaneDesc {
+    XY,
+    XZ,
+    YZ,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum AxisDesc {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate { offset: [f64; 3] },
+    Rotate { axis: AxisDesc, angle: f64 },
+    FlipNormal,
+}
+
+#[derive(Deserialize)]
+struct ObjectDesc {
+    /// Every other key, read as a `ShapeDesc` so that unknown keys are rejected, which
+    /// `deny_unknown_fields` cannot do next to a flattened field
+    #[serde(flatten)]
+    shape: toml::Table,
+    /// Optional for meshes, whose MTL materials take precedence
+    material: Option<String>,
+    #[serde(default)]
+    transforms: Vec<TransformDesc>,
+    /// Also sample this object as a light source, which spheres, quads and meshes of one model
+    /// support
+    #[serde(default)]
+    light: bool,
+    /// Fill the shape with a participating medium instead of giving it a surface
+    medium: Option<MediumDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    /// Density, or its peak when it varies
+    density: f64,
+    #[serde(default = "default_medium_albedo")]
+    albedo: TextureRef,
+    /// Henyey-Greenstein asymmetry of scattering, 0 for isotropic
+    #[serde(default)]
+    anisotropy: f64,
+    /// Vary the density with Perlin turbulence of this frequency
+    noise: Option<f64>,
+    /// Vary the density with a Mitsuba `.vol` grid, scaled by `density`
+    grid: Option<PathBuf>,
+}
+
+fn default_medium_albedo() -> TextureRef {
+    TextureRef::Color([1.0, 1.0, 1.0])
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        time0: f64,
+        time1: f64,
+        radius: f64,
+    },
+    Quad {
+        plane: PlaneDesc,
+        a: [f64; 2],
+        b: [f64; 2],
+        k: f64,
+    },
+    Cube {
+        min: [f64; 3],
+        max: [f64; 3],
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+    },
+    /// OBJ, PLY or STL file, told apart by extension
+    Mesh {
+        path: PathBuf,
+        #[serde(default)]
+        offset: [f64; 3],
+        #[serde(default = "default_scale")]
+        scale: f64,
+        /// Dihedral angle in degrees beyond which generated normals keep a hard edge, 0 for
+        /// faceted shading
+        #[serde(default = "default_crease_angle")]
+        crease_angle: f64,
+    },
+}
+
+impl ShapeDesc {
+    /// The `type` naming the shape in a scene file
+    fn kind(&self) -> &'static str {
+        match self {
+            ShapeDesc::Sphere { .. } => "sphere",
+            ShapeDesc::MovingSphere { .. } => "moving_sphere",
+            ShapeDesc::Quad { .. } => "quad",
+            ShapeDesc::Cube { .. } => "cube",
+            ShapeDesc::Triangle { .. } => "triangle",
+            ShapeDesc::Mesh { .. } => "mesh",
+        }
+    }
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// Turn a byte offset into the source into a 1-based `line:column`
+fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, col)
+}
+
+struct Loader<'a> {
+    path: &'a Path,
+    src: &'a str,
+    dir: PathBuf,
+    texture_descs: &'a BTreeMap<String, Spanned<TextureDesc>>,
+    material_descs: &'a BTreeMap<String, Spanned<MaterialDesc>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    rng: StdRng,
+    cfg: &'a Config,
+}
+
+impl<'a> Loader<'a> {
+    fn error(&self, span: Range<usize>, msg: impl std::fmt::Display) -> String {
+        let (line, col) = line_col(self.src, span.start);
+        format!("{}:{}:{}: {}", self.path.display(), line, col, msg)
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    fn texture_ref(
+        &mut self,
+        tex: &TextureRef,
+        span: Range<usize>,
+        visiting: &mut Vec<String>,
+    ) -> Result<Arc<dyn Texture>, String> {
+        match tex {
+            TextureRef::Color(color) => Ok(Arc::new(ConstantTexture::new(vec3(*color)))),
+            TextureRef::Named(name) => self.texture(name, span, visiting),
+        }
+    }
+
+    fn parameter(
+        &mut self,
+        param: &Option<ParameterRef>,
+        span: Range<usize>,
+    ) -> Result<Option<Parameter>, String> {
+        Ok(match param {
+            None => None,
+            Some(ParameterRef::Value(value)) => Some(Parameter::Constant(*value)),
+            Some(ParameterRef::Texture { texture, channel }) => {
+                if *channel > 2 {
+                    return Err(self.error(span, format!("no channel {} in a color", channel)));
+                }
+                let texture = self.texture(texture, span, &mut vec![])?;
+                Some(Parameter::Channel(texture, *channel))
+            }
+        })
+    }
+
+    fn texture(
+        &mut self,
+        name: &str,
+        span: Range<usize>,
+        visiting: &mut Vec<String>,
+    ) -> Result<Arc<dyn Texture>, String> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+        let descs = self.texture_descs;
+        let desc = descs
+            .get(name)
+            .ok_or_else(|| self.error(span, format!("unknown texture `{}`", name)))?;
+        if visiting.iter().any(|n| n == name) {
+            return Err(self.error(desc.span(), format!("texture `{}` refers to itself", name)));
+        }
+        visiting.push(name.to_string());
+
+        let texture: Arc<dyn Texture> = match desc.get_ref() {
+            TextureDesc::Constant { color } => Arc::new(ConstantTexture::new(vec3(*color))),
+            TextureDesc::Checker { odd, even } => Arc::new(CheckerTexture::new(
+                self.texture_ref(odd, desc.span(), visiting)?,
+                self.texture_ref(even, desc.span(), visiting)?,
+            )),
+            TextureDesc::Image { path } => {
+                let image = image::open(self.resolve(path))
+                    .map_err(|err| {
+                        self.error(
+                            desc.span(),
+                            format!("failed to open {}: {}", path.display(), err),
+                        )
+                    })?
+                    .to_rgb8();
+                let (width, height) = image.dimensions();
+                Arc::new(ImageTexture::new(image.into_raw(), width, height))
+            }
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new(*scale, &mut self.rng)),
+        };
+
+        visiting.pop();
+        self.textures.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn material(&mut self, name: &str, span: Range<usize>) -> Result<Arc<dyn Material>, String> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.clone());
+        }
+        let descs = self.material_descs;
+        let desc = descs
+            .get(name)
+            .ok_or_else(|| self.error(span, format!("unknown material `{}`", name)))?;
+
+        let material: Arc<dyn Material> =
+            match desc.get_ref() {
+                MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::new(
+                    self.texture_ref(albedo, desc.span(), &mut vec![])?,
+                )),
+                MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(vec3(*albedo), *fuzz)),
+                MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+                MaterialDesc::Conductor { eta, k, roughness } => {
+                    Arc::new(Conductor::new(vec3(*eta), vec3(*k), *roughness))
+                }
+                MaterialDesc::RoughDielectric { ir, roughness } => {
+                    Arc::new(RoughDielectric::new(*ir, *roughness))
+                }
+                MaterialDesc::Principled {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                    transmission,
+                    clearcoat,
+                    sheen,
+                    ior,
+                } => {
+                    let span = desc.span();
+                    let mut material =
+                        Principled::new(self.texture_ref(base_color, span.clone(), &mut vec![])?);
+                    let mut param = |p: &Option<ParameterRef>| self.parameter(p, span.clone());
+                    if let Some(p) = param(metallic)? {
+                        material = material.with_metallic(p);
+                    }
+                    if let Some(p) = param(roughness)? {
+                        material = material.with_roughness(p);
+                    }
+                    if let Some(p) = param(specular)? {
+                        material = material.with_specular(p);
+                    }
+                    if let Some(p) = param(transmission)? {
+                        material = material.with_transmission(p);
+                    }
+                    if let Some(p) = param(clearcoat)? {
+                        material = material.with_clearcoat(p);
+                    }
+                    if let Some(p) = param(sheen)? {
+                        material = material.with_sheen(p);
+                    }
+                    if let Some(ior) = ior {
+                        material = material.with_ior(*ior);
+                    }
+                    Arc::new(material)
+                }
+                MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight::new(
+                    self.texture_ref(emit, desc.span(), &mut vec![])?,
+                )),
+            };
+
+        self.materials.insert(name.to_string(), material.clone());
+        Ok(material)
+    }
+
+    fn object(&mut self, object: &Spanned<ObjectDesc>) -> Result<Arc<dyn Hittable>, String> {
+        let span = object.span();
+        let desc = object.get_ref();
+        let shape: ShapeDesc = toml::Value::Table(desc.shape.clone())
+            .try_into()
+            .map_err(|err: toml::de::Error| self.error(span.clone(), err.message()))?;
+        let material = match &desc.material {
+            Some(name) => Some(self.material(name, span.clone())?),
+            None => None,
+        };
+        // the boundary of a medium is never shaded
+        let material = match &desc.medium {
+            Some(_) => Some(Arc::new(Dielectric::new(1.0)) as Arc<dyn Material>),
+            None => material,
+        };
+        let required = material
+            .clone()
+            .ok_or_else(|| self.error(span.clone(), "missing field `material`"));
+        // only these can pick points on themselves for light sampling
+        if desc.light {
+            if desc.medium.is_some() {
+                return Err(self.error(span, "a medium cannot be sampled as a light"));
+            }
+            if !matches!(
+                shape,
+                ShapeDesc::Sphere { .. } | ShapeDesc::Quad { .. } | ShapeDesc::Mesh { .. }
+            ) {
+                return Err(self.error(
+                    span,
+                    format!("a {} cannot be sampled as a light", shape.kind()),
+                ));
+            }
+        }
+
+        let mut hittable: Box<dyn Hittable> = match &shape {
+            ShapeDesc::Sphere { center, radius } => {
+                Box::new(Sphere::new(vec3(*center), *radius, required?))
+            }
+            ShapeDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+            } => Box::new(MovingSphere::new(
+                vec3(*center0),
+                vec3(*center1),
+                *time0,
+                *time1,
+                *radius,
+                required?,
+            )),
+            ShapeDesc::Quad { plane, a, b, k } => {
+                let plane = match plane {
+                    PlaneDesc::XY => Plane::XY,
+                    PlaneDesc::XZ => Plane::XZ,
+                    PlaneDesc::YZ => Plane::YZ,
+                };
+                Box::new(Quad::new(plane, a[0], a[1], b[0], b[1], *k, required?))
+            }
+            ShapeDesc::Cube { min, max } => Box::new(Cube::new(vec3(*min), vec3(*max), required?)),
+            ShapeDesc::Triangle {
+                vertices,
+                normals,
+                uvs,
+            } => Box::new(Triangle::with_attributes(
+                vertices.map(vec3),
+                normals.map(|normals| normals.map(|n| vec3(n).unit())),
+                *uvs,
+                required?,
+            )),
+            ShapeDesc::Mesh {
+                path,
+                offset,
+                scale,
+                crease_angle,
+            } => {
+                let resolved = self.resolve(path);
+                let (offset, leaf_size) = (vec3(*offset), self.cfg.bvh_leaf_size);
+                let extension = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.to_ascii_lowercase());
+                let mut meshes = match extension.as_deref() {
+                    Some("ply") => {
+                        Mesh::load_ply(resolved, offset, *scale, material, *crease_angle, leaf_size)
+                            .map(|mesh| vec![mesh])
+                    }
+                    Some("stl") => Mesh::load_stl(
+                        resolved,
+                        offset,
+                        *scale,
+                        required?,
+                        *crease_angle,
+                        leaf_size,
+                    )
+                    .map(|mesh| vec![mesh]),
+                    _ => {
+                        Mesh::load_obj(resolved, offset, *scale, material, *crease_angle, leaf_size)
+                    }
+                }
+                .map_err(|err| self.error(span.clone(), err))?;
+                if desc.light && meshes.len() > 1 {
+                    return Err(self.error(
+                        span,
+                        "a mesh of several models cannot be sampled as a light",
+                    ));
+                }
+                if self.cfg.bvh_stats {
+                    for mesh in &meshes {
+                        println!("BVH {}: {}", mesh.name(), mesh.stats());
+                    }
+                }
+                if meshes.len() == 1 {
+                    Box::new(meshes.pop().unwrap())
+                } else {
+                    let models = meshes
+                        .into_iter()
+                        .map(|mesh| Box::new(mesh) as Box<dyn Hittable>)
+                        .collect();
+                    Box::new(build_bvh(
+                        &path.display().to_string(),
+                        models,
+                        0.0,
+                        1.0,
+                        self.cfg,
+                    ))
+                }
+            }
+        };
+
+        if let Some(medium) = &desc.medium {
+            hittable = self.medium(hittable, medium, span.clone())?;
+        }
+
+        for transform in &desc.transforms {
+            hittable = match transform {
+                TransformDesc::Translate { offset } => {
+                    Box::new(Translate::new(hittable, vec3(*offset)))
+                }
+                TransformDesc::Rotate { axis, angle } => {
+                    let axis = match axis {
+                        AxisDesc::X => Axis::X,
+                        AxisDesc::Y => Axis::Y,
+                        AxisDesc::Z => Axis::Z,
+                    };
+                    Box::new(Rotate::new(axis, hittable, *angle))
+                }
+                TransformDesc::FlipNormal => Box::new(FlipNormal::new(hittable)),
+            };
+        }
+
+        Ok(Arc::from(hittable))
+    }
+
+    fn medium(
+        &mut self,
+        boundary: Box<dyn Hittable>,
+        desc: &MediumDesc,
+        span: Range<usize>,
+    ) -> Result<Box<dyn Hittable>, String> {
+        let albedo = self.texture_ref(&desc.albedo, span.clone(), &mut vec![])?;
+        let phase: Arc<dyn Material> = if desc.anisotropy == 0.0 {
+            Arc::new(Isotropic::new(albedo))
+        } else {
+            Arc::new(HenyeyGreenstein::new(albedo, desc.anisotropy))
+        };
+        Ok(match (desc.noise, &desc.grid) {
+            (Some(_), Some(_)) => {
+                return Err(self.error(span, "a medium takes either `noise` or `grid`"))
+            }
+            (Some(scale), None) => {
+                let density = NoiseDensity::new(scale, desc.density, &mut self.rng);
+                Box::new(HeterogeneousMedium::new(boundary, density, phase))
+            }
+            (None, Some(path)) => {
+                let density = VoxelGrid::load(self.resolve(path), desc.density)
+                    .map_err(|err| self.error(span, err))?;
+                Box::new(HeterogeneousMedium::new(boundary, density, phase))
+            }
+            (None, None) => Box::new(ConstantMedium::with_phase(boundary, desc.density, phase)),
+        })
+    }
+}
+
+/// Load a scene description from a TOML file
+///
+/// Relative paths to images and meshes are resolved against the directory of the scene file.
+/// Errors are reported as `file:line:column: message`. The seed in `cfg` fixes procedural
+/// textures.
+#[allow(clippy::type_complexity)]
+pub fn load_scene<P: AsRef<Path>>(
+    path: P,
+    cfg: &Config,
+) -> Result<(Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera), String> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let desc: SceneDesc = toml::from_str(&src).map_err(|err| {
+        let msg = err.message();
+        match err.span() {
+            Some(span) => {
+                let (line, col) = line_col(&src, span.start);
+                format!("{}:{}:{}: {}", path.display(), line, col, msg)
+            }
+            None => format!("{}: {}", path.display(), msg),
+        }
+    })?;
+
+    let mut loader = Loader {
+        path,
+        src: &src,
+        dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        texture_descs: &desc.textures,
+        material_descs: &desc.materials,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        rng: StdRng::seed_from_u64(cfg.seed()),
+        cfg,
+    };
+
+    let camera_desc = &desc.camera;
+    let camera = Camera::new(
+        vec3(camera_desc.lookfrom),
+        vec3(camera_desc.lookat),
+        vec3(camera_desc.vup),
+        camera_desc.vfov,
+        cfg.aspect_ratio(),
+        camera_desc.aperture,
+        camera_desc
+            .focus_dist
+            .unwrap_or_else(|| (vec3(camera_desc.lookfrom) - vec3(camera_desc.lookat)).length()),
+        camera_desc.time0,
+        camera_desc.time1,
+    );
+
+    let mut world: Vec<Box<dyn Hittable>> = Vec::new();
+    let mut lights = HittableList::default();
+    for object in &desc.objects {
+        let hittable = loader.object(object)?;
+        if object.get_ref().light {
+            lights.list.push(Box::new(hittable.clone()));
+        }
+        world.push(Box::new(hittable));
+    }
+    if world.is_empty() {
+        return Err(format!("{}: scene has no objects", path.display()));
+    }
+
+    let world = build_bvh("world", world, camera_desc.time0, camera_desc.time1, cfg);
+    let bgcolor: Color = vec3(desc.background);
+
+    Ok((Box::new(world), Box::new(lights), bgcolor, camera))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// Load a scene of `objects` under a camera and a light material, returning the error
+    fn load_error(objects: &str) -> String {
+        let src = format!(
+            "[camera]\nlookfrom = [0.0, 0.0, 5.0]\nlookat = [0.0, 0.0, 0.0]\nvfov = 40.0\n\n\
+             [materials]\nlight = {{ type = \"diffuse_light\", emit = [1.0, 1.0, 1.0] }}\n\n{}",
+            objects
+        );
+        let path = std::env::temp_dir().join(format!("scene-{}.toml", std::process::id()));
+        fs::write(&path, src).unwrap();
+        let cfg = Config::try_parse_from(["raytracer"]).unwrap();
+        let loaded = load_scene(&path, &cfg);
+        fs::remove_file(&path).unwrap();
+        let Err(err) = loaded else {
+            panic!("scene was loaded");
+        };
+        err
+    }
+
+    #[test]
+    fn rejects_lights_that_cannot_be_sampled() {
+        let err = load_error(
+            "[[objects]]\ntype = \"cube\"\nmin = [0.0, 0.0, 0.0]\nmax = [1.0, 1.0, 1.0]\n\
+             material = \"light\"\nlight = true\n",
+        );
+        assert!(
+            err.ends_with(":9:1: a cube cannot be sampled as a light"),
+            "{}",
+            err
+        );
+
+        let err = load_error(
+            "[[objects]]\ntype = \"triangle\"\n\
+             vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]\n\
+             material = \"light\"\nlight = true\n",
+        );
+        assert!(
+            err.ends_with("a triangle cannot be sampled as a light"),
+            "{}",
+            err
+        );
+    }
+}
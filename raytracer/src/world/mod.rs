@@ -1,3 +1,5 @@
 pub mod camera;
+pub mod gltf_loader;
 pub mod hittablelist;
+pub mod loader;
 pub mod scene;
@@ -1,14 +1,19 @@
-﻿use rand::Rng;
+use std::{
+    iter,
+    path::{Path, PathBuf},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::geom::mesh::Mesh;
 use crate::geom::triangle::Triangle;
 use crate::hit::hittable::FlipNormal;
 use crate::{
-    cfg::ASPECT_RATIO,
+    cfg::{Config, SceneName},
     geom::quad::{Plane, Quad},
     geom::sphere::{MovingSphere, Sphere},
     geom::{
         cube::Cube,
+        medium::ConstantMedium,
         vec3::{Point3, Vec3},
     },
     hit::bvh::BVH,
@@ -28,25 +33,70 @@ use crate::{
 /// Select a exmaple scene
 ///
 /// Choices:
-/// - 1: Random scene
-/// - 2: Earth sphere
-/// - 3: Cornell box
-/// - 4: Final scene
-/// - 5: Cornell test
-/// - default: Random scene
-pub fn scene_select(scene: u8) -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
-    match scene {
-        1 => random_scene(),
-        2 => earth_sphere(),
-        3 => cornell_box(),
-        4 => weekend_final_scene(),
-        5 => cornell_test(),
-        _ => random_scene(),
+/// - random: Random scene
+/// - earth: Earth sphere
+/// - cornell: Cornell box
+/// - cornell-smoke: Cornell box with boxes of smoke
+/// - final: Final scene
+/// - cornell-test: Cornell test
+///
+/// The seed in `cfg` fixes the layout of the randomly generated scenes. Scenes that read images
+/// or models from disk fail if those are missing.
+#[allow(clippy::type_complexity)]
+pub fn scene_select(
+    cfg: &Config,
+) -> Result<(Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera), String> {
+    let aspect_ratio = cfg.aspect_ratio();
+    match cfg.scene {
+        SceneName::Random => Ok(random_scene(cfg)),
+        SceneName::Earth => earth_sphere(aspect_ratio),
+        SceneName::Cornell => Ok(cornell_box(aspect_ratio)),
+        SceneName::CornellSmoke => Ok(cornell_smoke(aspect_ratio)),
+        SceneName::Final => weekend_final_scene(cfg),
+        SceneName::CornellTest => cornell_test(cfg),
     }
 }
 
-fn random_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
-    let mut rng = rand::thread_rng();
+/// Where the asset `relative` to the repository is, looked for above the executable and then in
+/// the workspace it was built from, so that scenes render from any directory
+fn asset_path(relative: &str) -> PathBuf {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap_or(Path::new(env!("CARGO_MANIFEST_DIR")));
+    let exe = std::env::current_exe().ok();
+    let exe_dirs = exe.iter().flat_map(|exe| exe.ancestors().skip(1));
+    exe_dirs
+        .chain(iter::once(workspace))
+        .map(|dir| dir.join(relative))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| workspace.join(relative))
+}
+
+fn open_image(relative: &str) -> Result<image::RgbImage, String> {
+    let path = asset_path(relative);
+    image::open(&path)
+        .map(|image| image.to_rgb8())
+        .map_err(|err| format!("Failed to open {}: {}", path.display(), err))
+}
+
+/// Build a BVH with the leaf size from `cfg`, reporting its shape if asked to
+pub fn build_bvh(
+    name: &str,
+    objects: Vec<Box<dyn Hittable>>,
+    time0: f64,
+    time1: f64,
+    cfg: &Config,
+) -> BVH {
+    let bvh = BVH::with_leaf_size(objects, time0, time1, cfg.bvh_leaf_size);
+    if cfg.bvh_stats {
+        println!("BVH {}: {}", name, bvh.stats());
+    }
+    bvh
+}
+
+fn random_scene(cfg: &Config) -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
+    let aspect_ratio = cfg.aspect_ratio();
+    let mut rng = StdRng::seed_from_u64(cfg.seed());
     let mut world: Vec<Box<dyn Hittable>> = vec![];
 
     let ground_mat = Lambertian::new(CheckerTexture::new(
@@ -68,7 +118,8 @@ fn random_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
 
             if choose_mat < 0.8 {
                 // Diffuse
-                let albedo = Color::random_range(0.0..1.0) * Color::random_range(0.0..1.0);
+                let albedo = Color::random_range(&mut rng, 0.0..1.0)
+                    * Color::random_range(&mut rng, 0.0..1.0);
                 let sphere_mat = Lambertian::new(ConstantTexture::new(albedo));
                 let center1 = center + Vec3::new(0.0, rng.gen_range(0.0..0.5), 0.0);
                 let sphere = MovingSphere::new(center, center1, 0.0, 1.0, 0.2, sphere_mat);
@@ -76,7 +127,7 @@ fn random_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
                 world_add!(world, sphere);
             } else if choose_mat < 0.95 {
                 // Metal
-                let albedo = Color::random_range(0.4..1.0);
+                let albedo = Color::random_range(&mut rng, 0.4..1.0);
                 let fuzz = rng.gen_range(0.0..0.5);
                 let sphere_mat = Metal::new(albedo, fuzz);
                 let sphere = Sphere::new(center, 0.2, sphere_mat);
@@ -119,7 +170,7 @@ fn random_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
         lookat,
         vup,
         20.0,
-        ASPECT_RATIO,
+        aspect_ratio,
         aperture,
         dist_to_focus,
         0.0,
@@ -127,19 +178,18 @@ fn random_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
     );
 
     (
-        Box::new(BVH::new(world, 0.0, 1.0)),
+        Box::new(build_bvh("world", world, 0.0, 1.0, cfg)),
         Box::new(lights),
         bgcolor,
         camera,
     )
 }
 
-fn earth_sphere() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
-    let image = image::open(
-        "/home/hoi/Desktop/courses/2023-2024-1/Computer Graphics/labs/Rust_Ray_Tracer/img/e.jpg",
-    )
-    .expect("image not found")
-    .to_rgb8();
+#[allow(clippy::type_complexity)]
+fn earth_sphere(
+    aspect_ratio: f64,
+) -> Result<(Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera), String> {
+    let image = open_image("img/e.jpg")?;
     let (width, height) = image.dimensions();
     let img_data = image.into_raw();
     let texture = ImageTexture::new(img_data, width, height);
@@ -159,17 +209,17 @@ fn earth_sphere() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
         lookat,
         vup,
         20.0,
-        ASPECT_RATIO,
+        aspect_ratio,
         aperture,
         dist_to_focus,
         0.0,
         1.0,
     );
 
-    (Box::new(world), Box::new(lights), bgcolor, camera)
+    Ok((Box::new(world), Box::new(lights), bgcolor, camera))
 }
 
-fn cornell_box() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
+fn cornell_box(aspect_ratio: f64) -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
     let mut world = HittableList::default();
 
     let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
@@ -229,7 +279,91 @@ fn cornell_box() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
         lookat,
         vup,
         40.0,
-        ASPECT_RATIO,
+        aspect_ratio,
+        aperture,
+        dist_to_focus,
+        0.0,
+        1.0,
+    );
+
+    (Box::new(world), Box::new(lights), bgcolor, camera)
+}
+
+fn cornell_smoke(aspect_ratio: f64) -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
+    let mut world = HittableList::default();
+
+    let red = Lambertian::new(ConstantTexture::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Lambertian::new(ConstantTexture::new(Color::new(0.12, 0.45, 0.15)));
+    let light = DiffuseLight::new(ConstantTexture::new(Color::new(7.0, 7.0, 7.0)));
+    let rect_light = FlipNormal::new(Quad::new(
+        Plane::XZ,
+        113.0,
+        443.0,
+        127.0,
+        432.0,
+        554.0,
+        light,
+    ));
+
+    world.push(Quad::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.push(Quad::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.push(Quad::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white));
+    world.push(Quad::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+    world.push(Quad::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+    world.push(rect_light.clone());
+
+    let box1 = Translate::new(
+        Rotate::new(
+            Axis::Y,
+            Cube::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(165.0, 330.0, 165.0),
+                white,
+            ),
+            15.0,
+        ),
+        Vec3::new(265.0, 0.0, 295.0),
+    );
+    let box2 = Translate::new(
+        Rotate::new(
+            Axis::Y,
+            Cube::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(165.0, 165.0, 165.0),
+                white,
+            ),
+            -18.0,
+        ),
+        Vec3::new(130.0, 0.0, 65.0),
+    );
+    world.push(ConstantMedium::new(
+        box1,
+        0.01,
+        ConstantTexture::new(Color::zero()),
+    ));
+    world.push(ConstantMedium::new(
+        box2,
+        0.01,
+        ConstantTexture::new(Color::ones()),
+    ));
+
+    let mut lights = HittableList::default();
+    lights.push(rect_light);
+
+    let bgcolor = Color::new(0.0, 0.0, 0.0);
+
+    let lookfrom = Point3::new(278.0, 278.0, -800.0);
+    let lookat = Point3::new(278.0, 278.0, 0.0);
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    let dist_to_focus = 10.0;
+    let aperture = 0.0;
+    let camera = Camera::new(
+        lookfrom,
+        lookat,
+        vup,
+        40.0,
+        aspect_ratio,
         aperture,
         dist_to_focus,
         0.0,
@@ -239,10 +373,14 @@ fn cornell_box() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
     (Box::new(world), Box::new(lights), bgcolor, camera)
 }
 
-fn weekend_final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
+#[allow(clippy::type_complexity)]
+fn weekend_final_scene(
+    cfg: &Config,
+) -> Result<(Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera), String> {
+    let aspect_ratio = cfg.aspect_ratio();
     let mut world = HittableList::default();
 
-    let mut rng = rand::thread_rng();
+    let mut rng = StdRng::seed_from_u64(cfg.seed());
     let ground = Lambertian::new(ConstantTexture::new(Color::new(0.48, 0.83, 0.53)));
     let mut box_list1: Vec<Box<dyn Hittable>> = Vec::new();
     let boxes_per_side = 20;
@@ -262,7 +400,7 @@ fn weekend_final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera
             )));
         }
     }
-    world.push(BVH::new(box_list1, 0.0, 1.0));
+    world.push(build_bvh("ground", box_list1, 0.0, 1.0, cfg));
 
     let light = DiffuseLight::new(ConstantTexture::new(Color::new(7.0, 7.0, 7.0)));
     let rect_light = FlipNormal::new(Quad::new(
@@ -296,11 +434,21 @@ fn weekend_final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera
         Metal::new(Color::new(0.8, 0.8, 0.9), 1.0),
     ));
 
-    let image = image::open(
-        "/home/hoi/Desktop/courses/2023-2024-1/Computer Graphics/labs/Rust_Ray_Tracer/img/SJTU-Badge.png",
-    )
-    .expect("image not found")
-    .to_rgb8();
+    let boundary = Point3::new(360.0, 150.0, 145.0);
+    world.push(Sphere::new(boundary, 70.0, Dielectric::new(1.5)));
+    world.push(ConstantMedium::new(
+        Sphere::new(boundary, 70.0, Dielectric::new(1.5)),
+        0.2,
+        ConstantTexture::new(Color::new(0.2, 0.4, 0.9)),
+    ));
+    // thin mist over the whole scene
+    world.push(ConstantMedium::new(
+        Sphere::new(Point3::zero(), 5000.0, Dielectric::new(1.5)),
+        0.0001,
+        ConstantTexture::new(Color::ones()),
+    ));
+
+    let image = open_image("img/SJTU-Badge.png")?;
     let (nx, ny) = image.dimensions();
     println!("nx: {}, ny: {}", nx, ny);
     let data = image.into_raw();
@@ -313,7 +461,7 @@ fn weekend_final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera
     world.push(Sphere::new(
         Point3::new(220.0, 280.0, 300.0),
         80.0,
-        Lambertian::new(NoiseTexture::new(0.1)),
+        Lambertian::new(NoiseTexture::new(0.1, &mut rng)),
     ));
 
     let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
@@ -331,7 +479,11 @@ fn weekend_final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera
         )));
     }
     world.push(Translate::new(
-        Rotate::new(Axis::Y, BVH::new(box_list2, 0.0, 0.1), 15.0),
+        Rotate::new(
+            Axis::Y,
+            build_bvh("spheres", box_list2, 0.0, 0.1, cfg),
+            15.0,
+        ),
         Point3::new(-100.0, 270.0, 395.0),
     ));
 
@@ -350,17 +502,21 @@ fn weekend_final_scene() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera
         lookat,
         vup,
         40.0,
-        ASPECT_RATIO,
+        aspect_ratio,
         aperture,
         dist_to_focus,
         0.0,
         1.0,
     );
 
-    (Box::new(world), Box::new(lights), bgcolor, camera)
+    Ok((Box::new(world), Box::new(lights), bgcolor, camera))
 }
 
-fn cornell_test() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
+#[allow(clippy::type_complexity)]
+fn cornell_test(
+    cfg: &Config,
+) -> Result<(Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera), String> {
+    let aspect_ratio = cfg.aspect_ratio();
     let mut world = HittableList::default();
 
     let white = Lambertian::new(ConstantTexture::new(Color::new(0.73, 0.73, 0.73)));
@@ -369,48 +525,17 @@ fn cornell_test() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
     let dielectric = Dielectric::new(1.5);
     let metal = Metal::new(Color::new(0.8, 0.85, 0.88), 0.02);
     let light = DiffuseLight::new(ConstantTexture::new(Color::new(1.0, 1.0, 0.88) * 2.0));
-    let image = image::open(
-        "/home/hoi/Desktop/courses/2023-2024-1/Computer Graphics/labs/Rust_Ray_Tracer/img/SJTU_gate.jpg",
-    )
-    .expect("image not found")
-    .to_rgb8();
+    let image = open_image("img/SJTU_gate.jpg")?;
     let (nx, ny) = image.dimensions();
     println!("nx: {}, ny: {}", nx, ny);
     let data = image.into_raw();
     let gate_image_text = ImageTexture::new(data, nx, ny);
 
-    let obj = Mesh::load_obj("/home/hoi/Desktop/courses/2023-2024-1/Computer Graphics/labs/Rust_Ray_Tracer/objects/teapot.obj", Vec3::new(208.0, 55.0, 208.0), 1.0, metal).unwrap();
-    world.push(obj);
-
     world.push(Quad::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, violet));
     world.push(Quad::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, tomato));
-    world.push(Quad::new(
-        Plane::XZ,
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        white.clone(),
-    ));
-    world.push(Quad::new(
-        Plane::XZ,
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ));
-    world.push(Quad::new(
-        Plane::XY,
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ));
+    world.push(Quad::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 0.0, white));
+    world.push(Quad::new(Plane::XZ, 0.0, 555.0, 0.0, 555.0, 555.0, white));
+    world.push(Quad::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white));
 
     let rect_light = FlipNormal::new(Quad::new(
         Plane::XZ,
@@ -459,12 +584,12 @@ fn cornell_test() -> (Box<dyn Hittable>, Box<dyn Hittable>, Color, Camera) {
         lookat,
         vup,
         40.0,
-        ASPECT_RATIO,
+        aspect_ratio,
         aperture,
         dist_to_focus,
         0.0,
         1.0,
     );
 
-    (Box::new(world), Box::new(lights), bgcolor, camera)
+    Ok((Box::new(world), Box::new(lights), bgcolor, camera))
 }